@@ -3,13 +3,22 @@ use crate::render::Surface;
 use crate::vector_math;
 use crate::vector_math::Vector;
 
+/// A surface shaded with the Blinn-Phong model, with the parameters of an NFF
+/// fill color.
 pub struct BlinnPhong {
+    /// Color of the surface under white light
     pub color: Color,
+    /// Fraction of the color reflected diffusely
     pub diffuse_component: f32,
+    /// Fraction of the light reflected as specular highlights
     pub specular_component: f32,
+    /// Exponent controlling how tight the highlights are
     pub shine: f32,
+    /// Fraction of the light reflected as a mirror image of the scene
     pub reflectance: f32,
+    /// Fraction of the light passing through the surface
     pub transmittance: f32,
+    /// Index of refraction for light passing through
     pub refraction_index: f32
 }
 
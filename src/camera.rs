@@ -0,0 +1,244 @@
+use std::f32::consts::PI;
+use crate::vector_math;
+use crate::vector_math::{Vector, Point};
+use crate::render::View;
+use crate::sampling;
+use crate::sampling::Rng;
+
+/// Ways of mapping the image onto directions from the viewer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Standard pinhole camera; the field of view angle is vertical
+    Perspective,
+    /// Parallel rays, covering the same area at the distance of `at` that the
+    /// perspective projection would
+    Orthographic,
+    /// Equidistant fisheye, where the distance from the image center is
+    /// proportional to the angle from the view direction. The field of view
+    /// angle spans the height of the image and may be up to 360 degrees;
+    /// anything outside the circle it covers is left transparent.
+    Fisheye,
+    /// Full 360 x 180 degree panorama, with longitude across the image and
+    /// latitude up and down it; the image should be twice as wide as it is
+    /// high.
+    Equirectangular,
+    /// All six faces of a cube around the viewer, each with a 90 degree field
+    /// of view, laid out in a 3 x 2 grid:
+    ///
+    /// ```text
+    /// right, left,  up
+    /// down,  front, back
+    /// ```
+    ///
+    /// The image should be 3:2 so the faces are square.
+    CubeMap
+}
+
+impl Projection {
+    /// Look up a projection by the name used for it in NFF files and on the
+    /// command line.
+    pub fn from_name(name: &str) -> Option<Projection> {
+        match name {
+            "perspective" => Some(Projection::Perspective),
+            "orthographic" => Some(Projection::Orthographic),
+            "fisheye" => Some(Projection::Fisheye),
+            "equirectangular" => Some(Projection::Equirectangular),
+            "cube-map" => Some(Projection::CubeMap),
+            _ => None
+        }
+    }
+}
+
+// Everything needed to generate the primary ray through a point on the
+// image, derived from the view once up front.
+pub struct Camera {
+    projection: Projection,
+    from: Point,
+    // Unit vectors pointing forward, right and up from the viewer
+    forward: Vector,
+    right_direction: Vector,
+    up_direction: Vector,
+    // Vectors from the center of the perspective screen to its right and top
+    // edges, for a screen 1.0 in front of the viewer
+    right: Vector,
+    up: Vector,
+    // Distance from the viewer to `at`, which sets the size of the
+    // orthographic view
+    target_distance: f32,
+    // Half the field of view angle, in radians
+    half_angle: f32,
+    aspect_ratio: f32,
+    hither: f32,
+    yon: f32,
+    aperture: f32,
+    // Vectors spanning the lens, each as long as its radius
+    lens_right: Vector,
+    lens_up: Vector,
+    focal_distance: f32
+}
+
+impl Camera {
+    pub fn new(view: &View) -> Camera {
+        // All the perspective rays can be thought of as passing through a
+        // rectangular screen that is <near> away from the eye, with
+        // dimensions:
+        //      width:  aspect ratio * near * tan(fov/2)
+        //      height: near * tan(fov/2).
+        // Since we're normalizing the rays anyway, we can assume <near> is
+        // a distance of 1.0 in the direction <at> - <from>.
+
+        // Distance from the center of the screen to either edge
+        let up_len = (PI * view.angle / 360.0).tan();
+        let right_len = up_len * view.aspect_ratio();
+
+        // Vector from the eye to the center of the screen.
+        let forward = (&view.at - &view.from).normalized();
+        let view_up = view.rolled_up();
+
+        // Vector from the center of the screen to the right edge.
+        let right =
+            vector_math::cross(&forward, &view_up).normalized() * right_len;
+
+        // Vector from the center of the screen to the top edge; note view_up
+        // may not be perpendicular to forward, but this is.
+        let up = vector_math::cross(&right, &forward).normalized() * up_len;
+
+        // The directions are worked out separately, since the screen size
+        // is meaningless (and may be negative) for the wide-angle
+        // projections.
+        let right_direction =
+            vector_math::cross(&forward, &view_up).normalized();
+        let up_direction =
+            vector_math::cross(&right_direction, &forward).normalized();
+
+        Camera {
+            projection: view.projection,
+            from: view.from.clone(),
+            target_distance: (&view.at - &view.from).magnitude(),
+            half_angle: PI * view.angle / 360.0,
+            aspect_ratio: view.aspect_ratio(),
+            aperture: view.aperture,
+            lens_right: right.normalized() * view.aperture,
+            lens_up: up.normalized() * view.aperture,
+            focal_distance: view.focus(),
+            forward,
+            right_direction,
+            up_direction,
+            right,
+            up,
+            hither: view.hither,
+            yon: view.yon.unwrap_or(f32::INFINITY)
+        }
+    }
+
+    // Near-clip distance for the camera's rays
+    pub fn hither(&self) -> f32 {
+        self.hither
+    }
+
+    // Far-clip distance for the camera's rays, or infinity if there isn't
+    // one
+    pub fn yon(&self) -> f32 {
+        self.yon
+    }
+
+    // Origin and direction of a ray through the point (`sx`, `sy`) on the
+    // screen, where both coordinates range from -1.0 to 1.0 and +y is up.
+    // With a non-zero aperture, the ray starts from a random point on the
+    // lens, drawn from `rng`. Depth of field only applies to the
+    // perspective and orthographic projections.
+    //
+    // Returns None if the point is outside the area the projection covers.
+    pub fn ray(&self, sx: f32, sy: f32, rng: &mut Rng) ->
+            Option<(Point, Vector)> {
+        match self.projection {
+            Projection::Perspective => {
+                let direction = (&self.forward + &self.up * sy +
+                    &self.right * sx).normalized();
+                Some(self.thin_lens(self.from.clone(), direction, rng))
+            },
+            Projection::Orthographic => {
+                let origin = &self.from +
+                    (&self.up * sy + &self.right * sx) * self.target_distance;
+                Some(self.thin_lens(origin, self.forward.clone(), rng))
+            },
+            Projection::Fisheye => {
+                // Measure in units of the image height, so the field of view
+                // angle spans it exactly.
+                let x = sx * self.aspect_ratio;
+                let radius = (x * x + sy * sy).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+                if radius == 0.0 {
+                    return Some((self.from.clone(), self.forward.clone()));
+                }
+
+                let (sin, cos) = (radius * self.half_angle).sin_cos();
+                let direction = &self.forward * cos +
+                    (&self.right_direction * x + &self.up_direction * sy) *
+                        (sin / radius);
+                Some((self.from.clone(), direction.normalized()))
+            },
+            Projection::Equirectangular => {
+                let (sin_longitude, cos_longitude) = (sx * PI).sin_cos();
+                let (sin_latitude, cos_latitude) = (sy * PI / 2.0).sin_cos();
+                let direction =
+                    &self.forward * (cos_latitude * cos_longitude) +
+                    &self.right_direction * (cos_latitude * sin_longitude) +
+                    &self.up_direction * sin_latitude;
+                Some((self.from.clone(), direction.normalized()))
+            },
+            Projection::CubeMap => {
+                // Find which face the point is on, and where on that face,
+                // with both face coordinates in [-1.0, 1.0] and +y up.
+                let u = (sx + 1.0) * 1.5;
+                let v = 1.0 - sy;
+                let column = (u.floor() as usize).min(2);
+                let row = (v.floor() as usize).min(1);
+                let face_x = 2.0 * (u - column as f32) - 1.0;
+                let face_y = 1.0 - 2.0 * (v - row as f32);
+
+                let (forward, right, up) = self.cube_face(row * 3 + column);
+                let direction = forward + right * face_x + up * face_y;
+                Some((self.from.clone(), direction.normalized()))
+            }
+        }
+    }
+
+    // Forward, right and up vectors of the cube map face with the given
+    // index in the layout.
+    fn cube_face(&self, index: usize) -> (Vector, Vector, Vector) {
+        let forward = &self.forward;
+        let right = &self.right_direction;
+        let up = &self.up_direction;
+        match index {
+            0 => (right.clone(), -forward, up.clone()),
+            1 => (-right, forward.clone(), up.clone()),
+            2 => (up.clone(), right.clone(), -forward),
+            3 => (-up, right.clone(), forward.clone()),
+            4 => (forward.clone(), right.clone(), up.clone()),
+            _ => (-forward, -right, up.clone())
+        }
+    }
+
+    // Move the origin of the ray from `origin` in `direction` to a random
+    // point on the lens, if the camera has one.
+    fn thin_lens(&self, origin: Point, direction: Vector, rng: &mut Rng) ->
+            (Point, Vector) {
+        if self.aperture <= 0.0 {
+            return (origin, direction);
+        }
+
+        // Rays through every point on the lens converge where the pinhole
+        // ray meets the focal plane, so objects on that plane are sharp
+        // and everything else is blurred.
+        let focal_point = &origin +
+            &direction * (self.focal_distance / direction.dot(&self.forward));
+        let (u, v) = sampling::unit_disk(rng);
+        let lens_origin = &origin + &self.lens_right * u + &self.lens_up * v;
+
+        let direction = (&focal_point - &lens_origin).normalized();
+        (lens_origin, direction)
+    }
+}
@@ -1,13 +1,19 @@
 use std::ops;
 
+/// A linear RGB color. Components normally range from 0.0 to 1.0, but
+/// lights and high dynamic range renders can go higher.
 #[derive(Debug, Clone)]
 pub struct Color {
+    /// Red component
     pub r: f32,
+    /// Green component
     pub g: f32,
+    /// Blue component
     pub b: f32
 }
 
 impl Color {
+    /// Limit each component to at most 1.0.
     pub fn clamp(&mut self) {
         if self.r > 1.0 {
             self.r = 1.0;
@@ -20,6 +26,7 @@ impl Color {
         }
     }
 
+    /// Color with every component 0.0
     pub fn black() -> Color {
         Color {
             r: 0.0,
@@ -28,6 +35,7 @@ impl Color {
         }
     }
 
+    /// Color with every component 1.0
     pub fn white() -> Color {
         Color {
             r: 1.0,
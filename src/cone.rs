@@ -1,18 +1,85 @@
 use crate::vector_math;
 use crate::vector_math::{Point, Vector};
-use crate::shape::{Shape, IntersectResult, BoundingBox};
+use crate::shape::{Shape, IntersectResult, BoundingBox, Interval};
+use crate::csg;
+use crate::disk::Disk;
 
-// Generalized cone & cylinder - cones have apex_radius 0, cylinders have
-// apex_radius = base_radius, cone frustums are somewhere in between.
+/// Generalized cone & cylinder - cones have apex_radius 0, cylinders have
+/// apex_radius = base_radius, cone frustums are somewhere in between.
 pub struct Cone {
-    pub base: Point,
-    pub apex: Point,
-    pub base_radius: f32,
-    pub apex_radius: f32
+    base: Point,
+    apex: Point,
+    base_radius: f32,
+    apex_radius: f32,
+    // Unit vectors u and v across the cone and w up its center line, from
+    // the base to the apex
+    basis: [Vector; 3],
+    // Distance from the base to the apex
+    length: f32,
+    // Flat disks closing the base and apex ends, if the cone is capped;
+    // otherwise it's an open tube, as in NFF.
+    caps: Option<[Disk; 2]>
+}
+
+impl Cone {
+    /// Open cone from the center of the base end, with `base_radius`, to
+    /// the center of the apex end, with `apex_radius`.
+    pub fn new(base: Point, apex: Point, base_radius: f32, apex_radius: f32) ->
+            Cone {
+        let base_to_apex = &apex - &base;
+        let w = base_to_apex.normalized();
+
+        // To get the next basis vector, we can cross w with anything that
+        // is not a multiple of w. To avoid this we'll pick the axis
+        // corresponding to w's smallest component.
+        let shortest_w_component =
+            if w.dx.abs() < w.dy.abs() && w.dx.abs() < w.dz.abs() {
+                Vector {dx: 1.0, dy: 0.0, dz: 0.0}
+            } else if w.dy.abs() < w.dz.abs() {
+                Vector {dx: 0.0, dy: 1.0, dz: 0.0}
+            } else {
+                Vector {dx: 0.0, dy: 0.0, dz: 1.0}
+            };
+
+        // Note: u & v will already be normalized
+        let u = vector_math::cross(&w, &shortest_w_component);
+        let v = vector_math::cross(&w, &u);
+
+        Cone {
+            base,
+            apex,
+            base_radius,
+            apex_radius,
+            basis: [u, v, w],
+            length: base_to_apex.magnitude(),
+            caps: None
+        }
+    }
+
+    /// Close the ends with flat disks.
+    pub fn capped(self) -> Cone {
+        // The caps face out of the ends, away from each other.
+        let axis = &self.basis[2];
+        let caps = [
+            Disk {
+                center: self.base.clone(),
+                normal: -axis,
+                radius: self.base_radius
+            },
+            Disk {
+                center: self.apex.clone(),
+                normal: axis.clone(),
+                radius: self.apex_radius
+            }];
+        Cone {
+            caps: Some(caps),
+            ..self
+        }
+    }
 }
 
 impl Shape for Cone {
-    fn bounding_box(&self) -> BoundingBox {
+    fn bounding_box(&self) -> Option<BoundingBox> {
         // Ideally the box surrounds the central line of the cone and is
         // extended out by the x/y/z components of the base and apex to form
         // a tight bound. This can be calculated using projections:
@@ -30,9 +97,9 @@ impl Shape for Cone {
         let unit_x = Vector {dx: 1.0, dy: 0.0, dz: 0.0};
         let unit_y = Vector {dx: 0.0, dy: 1.0, dz: 0.0};
         let unit_z = Vector {dx: 0.0, dy: 0.0, dz: 1.0};
-        let n = (&self.apex - &self.base).normalized();
+        let n = &self.basis[2];
 
-        let px = unit_x - (&n * n.dx);
+        let px = unit_x - (n * n.dx);
         let mx = px.magnitude();
         let scale_x = if mx > 0.0 {
             px.dx / mx 
@@ -40,7 +107,7 @@ impl Shape for Cone {
             0.0
         };
 
-        let py = unit_y - (&n * n.dy);
+        let py = unit_y - (n * n.dy);
         let my = py.magnitude();
         let scale_y = if my > 0.0 {
             py.dy / my
@@ -48,7 +115,7 @@ impl Shape for Cone {
             0.0
         };
 
-        let pz = unit_z - (&n * n.dz);
+        let pz = unit_z - (n * n.dz);
         let mz = pz.magnitude();
         let scale_z = if mz > 0.0 {
             pz.dz / mz
@@ -80,7 +147,7 @@ impl Shape for Cone {
         let max_corner_z = (self.base.z + base_z_extra).max(
             self.apex.z + apex_z_extra);
 
-        BoundingBox {
+        Some(BoundingBox {
             corner: Point {
                 x: min_corner_x,
                 y: min_corner_y,
@@ -91,11 +158,48 @@ impl Shape for Cone {
                 dy: max_corner_y - min_corner_y,
                 dz: max_corner_z - min_corner_z
             }
+        })
+    }
+
+    // Only a capped cone encloses anything, and like the cylinder or
+    // frustum it can be, it's convex.
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        if self.caps.is_some() {
+            Some(csg::convex_intervals(self, src, ray))
+        } else {
+            None
         }
     }
 
     fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
             Option<IntersectResult> {
+        let side = self.intersect_side(src, ray, near);
+        let caps = match &self.caps {
+            Some(caps) => caps,
+            None => return side
+        };
+
+        let mut nearest = side;
+        for cap in caps {
+            if let Some(hit) = cap.intersect(src, ray, near) {
+                let nearer = match &nearest {
+                    Some(prior) => hit.dist < prior.dist,
+                    None => true
+                };
+                if nearer {
+                    nearest = Some(hit);
+                }
+            }
+        }
+        nearest
+    }
+}
+
+impl Cone {
+    // Intersect the ray with the sloping side of the cone only, ignoring
+    // the caps.
+    fn intersect_side(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
         // Notes copied from the C++ version...
         //
         // It's a good five pages of derivations to get
@@ -111,48 +215,30 @@ impl Shape for Cone {
         // First we're going to want to change to a new basis,
         // with one axis (w) going up the center of the cylinder,
         // and the others (u and v) in the plane of the base.
+        // That's worked out once, when the cone is made.
 
-        let base_to_apex = &self.apex - &self.base;
-        let w = base_to_apex.normalized();
-
-        // To get the next basis vector, we can cross w with
-        // anything that is not a multiple of w.  To avoid this
-        // we'll pick the axis corresponding to w's smallest
-        // component.
-
-        let shortest_w_component =
-            if w.dx.abs() < w.dy.abs() && w.dx.abs() < w.dz.abs() {
-                Vector {dx: 1.0, dy: 0.0, dz: 0.0}
-            } else if w.dy.abs() < w.dz.abs() {
-                Vector {dx: 0.0, dy: 1.0, dz: 0.0}
-            } else {
-                Vector {dx: 0.0, dy: 0.0, dz: 1.0}
-            };
-
-        // Note: u & v will already be normalized
-        let u = vector_math::cross(&w, &shortest_w_component);
-        let v = vector_math::cross(&w, &u);
+        let [u, v, w] = &self.basis;
 
         // Now we need to convert the ray and src to the new
         // basis, which has its origin at the base.
 
         let base_to_src = src - &self.base;
         let src_uvw = Vector {
-            dx: vector_math::dot(&base_to_src, &u),
-            dy: vector_math::dot(&base_to_src, &v),
-            dz: vector_math::dot(&base_to_src, &w)
+            dx: vector_math::dot(&base_to_src, u),
+            dy: vector_math::dot(&base_to_src, v),
+            dz: vector_math::dot(&base_to_src, w)
         };
 
         let ray_uvw = Vector {
-            dx: vector_math::dot(&ray, &u),
-            dy: vector_math::dot(&ray, &v),
-            dz: vector_math::dot(&ray, &w)
+            dx: vector_math::dot(ray, u),
+            dy: vector_math::dot(ray, v),
+            dz: vector_math::dot(ray, w)
         };
 
         // Now we can do the actual computation, which is
         // actually very ugly...
 
-        let mag = base_to_apex.magnitude();
+        let mag = self.length;
         let dr = self.base_radius - self.apex_radius;
 
         let a = ray_uvw.dx * ray_uvw.dx +
@@ -175,6 +261,10 @@ impl Shape for Cone {
             let sq = b2m4ac.sqrt();
             let r1 = (-b - sq) / (2.0 * a);
             let r2 = (-b + sq) / (2.0 * a);
+
+            // a is negative for rays that run more steeply than the side of
+            // a cone, which swaps the order of the roots.
+            let (r1, r2) = if r1 < r2 {(r1, r2)} else {(r2, r1)};
             
             // The intersection point is located at rn * ray_uvw + src_uvw
             // in the new space, but it's only guaranteed to be on the
@@ -186,19 +276,23 @@ impl Shape for Cone {
             let w2 = r2 * ray_uvw.dz + src_uvw.dz;
 
             let result =
-                if r1 < r2 && r1 >= near && w1 >= 0.0 && w1 <= mag {
-                    r1
+                if r1 >= near && w1 >= 0.0 && w1 <= mag {
+                    Some(r1)
                 } else if r2 >= near && w2 >= 0.0 && w2 <= mag {
-                    r2
+                    Some(r2)
                 } else {
-                    -1.0
+                    None
                 };
 
-            if result >= near {
+            if let Some(result) = result {
+                // The normal is the gradient of u^2 + v^2 - r^2, where the
+                // radius r = B - A * w shrinks by dr over the length.
+                let hit_w = result * ray_uvw.dz + src_uvw.dz;
+                let radius = self.base_radius - dr * hit_w / mag;
                 let normal_uvw = Vector {
-                    dx: (result * ray_uvw.dx + src_uvw.dx) * mag,
-                    dy: (result * ray_uvw.dy + src_uvw.dy) * mag,
-                    dz: dr
+                    dx: result * ray_uvw.dx + src_uvw.dx,
+                    dy: result * ray_uvw.dy + src_uvw.dy,
+                    dz: radius * dr / mag
                 };
 
                 let normal = Vector {
@@ -219,4 +313,58 @@ impl Shape for Cone {
 
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn side_normal() {
+        let cone = Cone::new(Point {x: 0.0, y: 0.0, z: 0.0},
+            Point {x: 0.0, y: 2.0, z: 0.0}, 2.0, 0.0);
+
+        // The side slopes at 45 degrees, and is 1.5 from the axis at y = 0.5.
+        let hit = cone.intersect(&Point {x: 5.0, y: 0.5, z: 0.0},
+            &Vector {dx: -1.0, dy: 0.0, dz: 0.0}, 0.0).unwrap();
+        assert!((hit.dist - 3.5).abs() < 1e-5);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let expected = Vector {dx: half, dy: half, dz: 0.0};
+        assert!((&hit.normal - &expected).magnitude() < 1e-5,
+            "{:?}", hit.normal);
+    }
+
+    #[test]
+    fn capped_ends() {
+        let base = Point {x: 1.0, y: 0.0, z: 0.0};
+        let apex = Point {x: 1.0, y: 0.0, z: 4.0};
+        let open = Cone::new(base.clone(), apex.clone(), 1.0, 0.5);
+        let capped = Cone::new(base, apex, 1.0, 0.5).capped();
+        let up = Vector {dx: 0.0, dy: 0.0, dz: 1.0};
+
+        // Along the center line, the open tube is missed altogether.
+        let src = Point {x: 1.0, y: 0.0, z: -2.0};
+        assert!(open.intersect(&src, &up, 0.0).is_none());
+        assert!(open.intervals(&src, &up).is_none());
+
+        // The caps face out of the ends.
+        let hit = capped.intersect(&src, &up, 0.0).unwrap();
+        assert!((hit.dist - 2.0).abs() < 1e-5);
+        assert!((&hit.normal + &up).magnitude() < 1e-5);
+        let hit = capped.intersect(&src, &up, 3.0).unwrap();
+        assert!((hit.dist - 6.0).abs() < 1e-5);
+        assert!((&hit.normal - &up).magnitude() < 1e-5);
+
+        let intervals = capped.intervals(&src, &up).unwrap();
+        assert_eq!(intervals.len(), 1);
+        assert!((intervals[0].enter.dist - 2.0).abs() < 1e-5);
+        assert!((intervals[0].exit.dist - 6.0).abs() < 1e-5);
+
+        // Past the narrower apex cap, the ray goes through the side.
+        let src = Point {x: 1.8, y: 0.0, z: 6.0};
+        let down = -&up;
+        let hit = capped.intersect(&src, &down, 0.0).unwrap();
+        assert!((hit.dist - 4.4).abs() < 1e-4);
+        assert!(hit.normal.dx > 0.0 && hit.normal.dz > 0.0);
+    }
+}
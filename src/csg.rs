@@ -0,0 +1,374 @@
+use std::cmp::Ordering;
+use crate::vector_math::{Point, Vector};
+use crate::shape::{Shape, IntersectResult, BoundingBox, Interval};
+
+
+/// Ways of combining two solids with constructive solid geometry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Inside either shape
+    Union,
+    /// Inside both shapes
+    Intersection,
+    /// Inside the first shape but not the second, which is cut away from it
+    Difference
+}
+
+/// Two solid shapes combined by constructive solid geometry. Either shape
+/// can be another Csg, so any number of solids can be built up into a tree.
+/// Shapes that don't enclose a solid (see `Shape::intervals`) count as empty.
+pub struct Csg {
+    /// How the solids are combined
+    pub operation: Operation,
+    /// First solid, which the second is cut from for a difference
+    pub first: Box<dyn Shape>,
+    /// Second solid
+    pub second: Box<dyn Shape>
+}
+
+// A point where the ray crosses the surface of a solid, going into it or
+// out of it.
+pub(crate) struct Crossing {
+    pub hit: IntersectResult,
+    pub entering: bool
+}
+
+// The intervals of a convex solid, which a line can only pass through once.
+// It enters at the nearest intersection along the whole line, and leaves at
+// the nearest intersection going the other way.
+pub(crate) fn convex_intervals(shape: &dyn Shape, src: &Point, ray: &Vector)
+        -> Vec<Interval> {
+    let enter = match shape.intersect(src, ray, f32::NEG_INFINITY) {
+        Some(enter) => enter,
+        None => return vec![]
+    };
+    let exit = match shape.intersect(src, &-ray, f32::NEG_INFINITY) {
+        // The normal points out of the solid either way; only the distance
+        // is measured backwards.
+        Some(exit) => IntersectResult {
+            normal: exit.normal,
+            dist: -exit.dist
+        },
+        None => return vec![]
+    };
+    vec![Interval {enter, exit}]
+}
+
+// Whether `shape` encloses a solid. Shapes give intervals for every ray or
+// for none, so any ray will do to ask.
+pub(crate) fn is_solid(shape: &dyn Shape) -> bool {
+    shape.intervals(&Point::origin(), &Vector {dx: 0.0, dy: 0.0, dz: 1.0})
+        .is_some()
+}
+
+// Stretches of the ray inside any of the solids that `crossings` came from,
+// which may overlap one another.
+pub(crate) fn union(crossings: Vec<Crossing>) -> Vec<Interval> {
+    let crossings = crossings.into_iter()
+        .map(|crossing| (crossing, false))
+        .collect();
+    sweep(crossings, Operation::Union)
+}
+
+// Stretches of the ray inside the solid enclosed by a surface made of
+// pieces, given where it crosses each of them. A ray through an edge or
+// corner shared by several pieces crosses them all at (nearly) the same
+// distance but only goes in or out once, and a ray grazing the surface goes
+// in and straight back out, so crossings that close together count once,
+// in whichever direction most of them go.
+pub(crate) fn closed_surface(mut crossings: Vec<Crossing>) -> Vec<Interval> {
+    crossings.sort_by(|a, b| a.hit.dist.total_cmp(&b.hit.dist));
+
+    let mut merged = vec![];
+    let mut crossings = crossings.into_iter().peekable();
+    while let Some(first) = crossings.next() {
+        let start = first.hit.dist;
+        let tolerance = 1e-5 * start.abs().max(1.0);
+
+        let mut net = 0;
+        let (mut going_in, mut going_out) = (None, None);
+        let mut next = Some(first);
+        while let Some(crossing) = next {
+            if crossing.entering {
+                net += 1;
+                going_in = going_in.or(Some(crossing));
+            } else {
+                net -= 1;
+                going_out = going_out.or(Some(crossing));
+            }
+            next = crossings.next_if(|crossing|
+                crossing.hit.dist - start <= tolerance);
+        }
+
+        match net.cmp(&0) {
+            Ordering::Greater => merged.extend(going_in),
+            Ordering::Less => merged.extend(going_out),
+            Ordering::Equal => {}
+        }
+    }
+    union(merged)
+}
+
+// Walk along the ray through the crossings of two sets of solids, tagged
+// with whether they belong to the second set, and keep the stretches that
+// are inside the result of `operation`. Each set is counted as inside
+// wherever the ray has entered more of its solids than it's left, so that
+// solids within a set can overlap.
+fn sweep(mut crossings: Vec<(Crossing, bool)>, operation: Operation) ->
+        Vec<Interval> {
+    crossings.sort_by(|(a, _), (b, _)| a.hit.dist.total_cmp(&b.hit.dist));
+
+    let mut depths = [0usize; 2];
+    let mut enter: Option<IntersectResult> = None;
+    let mut intervals = vec![];
+
+    for (crossing, second) in crossings {
+        let depth = &mut depths[second as usize];
+        if crossing.entering {
+            *depth += 1;
+        } else {
+            // Leaving a solid that was never entered can only be rounding
+            // error at a tangent or a seam.
+            *depth = depth.saturating_sub(1);
+        }
+
+        let (in_first, in_second) = (depths[0] > 0, depths[1] > 0);
+        let inside = match operation {
+            Operation::Union => in_first || in_second,
+            Operation::Intersection => in_first && in_second,
+            Operation::Difference => in_first && !in_second
+        };
+        if inside == enter.is_some() {
+            continue;
+        }
+
+        // Where the second solid is cut away from the first, its surface
+        // becomes the surface of the result facing the other way.
+        let mut hit = crossing.hit;
+        if second && operation == Operation::Difference {
+            hit.normal = -&hit.normal;
+        }
+
+        match enter.take() {
+            None => enter = Some(hit),
+            Some(enter) => intervals.push(Interval {enter, exit: hit})
+        }
+    }
+
+    intervals
+}
+
+// Stretches of the ray inside the result of `operation` on two solids, from
+// the stretches inside each of them
+pub(crate) fn combine(operation: Operation,
+        first: Vec<Interval>,
+        second: Vec<Interval>) -> Vec<Interval> {
+    let crossings = crossings_of(first).map(|crossing| (crossing, false))
+        .chain(crossings_of(second).map(|crossing| (crossing, true)))
+        .collect();
+    sweep(crossings, operation)
+}
+
+// Where the ray goes into and out of each of `intervals`
+pub(crate) fn crossings_of(intervals: Vec<Interval>) ->
+        impl Iterator<Item = Crossing> {
+    intervals.into_iter().flat_map(|interval| [
+        Crossing {hit: interval.enter, entering: true},
+        Crossing {hit: interval.exit, entering: false}])
+}
+
+impl Shape for Csg {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let first = self.first.bounding_box();
+        let second = self.second.bounding_box();
+        match self.operation {
+            Operation::Union => Some(first?.expand_to_fit(&second?)),
+            Operation::Intersection => match (first, second) {
+                (Some(first), Some(second)) => Some(first.overlap(&second)),
+                (first, second) => first.or(second)
+            },
+            Operation::Difference => first
+        }
+    }
+
+    // Rays leaving the surface of one part can go on to hit another.
+    fn is_composite(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        let first = self.first.intervals(src, ray).unwrap_or_default();
+        let second = self.second.intervals(src, ray).unwrap_or_default();
+        Some(combine(self.operation, first, second))
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        self.intervals(src, ray)?.into_iter()
+            .flat_map(|interval| [interval.enter, interval.exit])
+            .find(|hit| hit.dist >= near && hit.dist.is_finite())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use crate::vector_math;
+    use crate::sphere::Sphere;
+    use crate::mesh::{MeshBuilder, TriangleMesh};
+    use crate::instance::Group;
+
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {x, y, z}
+    }
+
+    fn vector(dx: f32, dy: f32, dz: f32) -> Vector {
+        Vector {dx, dy, dz}
+    }
+
+    // Stretch of a ray along +z, with the normals facing out of it
+    fn interval(enter: f32, exit: f32) -> Interval {
+        Interval {
+            enter: IntersectResult {
+                normal: vector(0.0, 0.0, -1.0),
+                dist: enter
+            },
+            exit: IntersectResult {normal: vector(0.0, 0.0, 1.0), dist: exit}
+        }
+    }
+
+    fn crossing(dist: f32, entering: bool) -> Crossing {
+        let dz = if entering {-1.0} else {1.0};
+        Crossing {
+            hit: IntersectResult {normal: vector(0.0, 0.0, dz), dist},
+            entering
+        }
+    }
+
+    fn ends(intervals: &[Interval]) -> Vec<(f32, f32)> {
+        intervals.iter()
+            .map(|interval| (interval.enter.dist, interval.exit.dist))
+            .collect()
+    }
+
+    fn assert_ends(intervals: &[Interval], expected: &[(f32, f32)]) {
+        let actual = ends(intervals);
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual.0 - expected.0).abs() < 1e-5 &&
+                (actual.1 - expected.1).abs() < 1e-5,
+                "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn combine_intervals() {
+        let first = || vec![interval(0.0, 2.0), interval(4.0, 6.0)];
+        let second = || vec![interval(1.0, 5.0)];
+
+        assert_eq!(ends(&combine(Operation::Union, first(), second())),
+            [(0.0, 6.0)]);
+        assert_eq!(
+            ends(&combine(Operation::Intersection, first(), second())),
+            [(1.0, 2.0), (4.0, 5.0)]);
+
+        let difference = combine(Operation::Difference, first(), second());
+        assert_eq!(ends(&difference), [(0.0, 1.0), (5.0, 6.0)]);
+        // The second solid's surface is turned to face out of what's left.
+        assert_eq!(difference[0].exit.normal.dz, 1.0);
+        assert_eq!(difference[1].enter.normal.dz, -1.0);
+    }
+
+    #[test]
+    fn union_of_overlapping_solids() {
+        let crossings = crossings_of(
+            vec![interval(0.0, 3.0), interval(1.0, 2.0), interval(5.0, 6.0)])
+            .collect();
+        assert_eq!(ends(&union(crossings)), [(0.0, 3.0), (5.0, 6.0)]);
+    }
+
+    #[test]
+    fn closed_surface_merges_crossings() {
+        // Through an edge on the way in and a corner on the way out
+        let crossings = vec![crossing(1.0, true), crossing(1.000001, true),
+            crossing(3.0, false), crossing(3.0, false), crossing(3.0, false)];
+        assert_eq!(ends(&closed_surface(crossings)), [(1.0, 3.0)]);
+
+        // Grazing an edge on the way through
+        let crossings = vec![crossing(1.0, true), crossing(2.0, false),
+            crossing(2.0, true), crossing(3.0, false)];
+        assert_eq!(ends(&closed_surface(crossings)), [(1.0, 3.0)]);
+    }
+
+    #[test]
+    fn csg_of_spheres() {
+        let solid = |operation| Csg {
+            operation,
+            first: Box::new(Sphere {center: point(0.0, 0.0, 0.0), radius: 2.0}),
+            second: Box::new(Sphere {center: point(0.0, 0.0, 3.0), radius: 2.0})
+        };
+        let intervals = |operation| solid(operation)
+            .intervals(&point(0.0, 0.0, -5.0), &vector(0.0, 0.0, 1.0))
+            .unwrap();
+
+        assert_ends(&intervals(Operation::Union), &[(3.0, 10.0)]);
+        assert_ends(&intervals(Operation::Intersection), &[(6.0, 7.0)]);
+        assert_ends(&intervals(Operation::Difference), &[(3.0, 6.0)]);
+    }
+
+    // The tetrahedron with corners at the origin and one along each axis,
+    // as a closed surface, with the normal at each corner from `normal`
+    fn tetrahedron(normal: impl Fn(&Point) -> Vector) -> Group {
+        let corners = [point(0.0, 0.0, 0.0), point(1.0, 0.0, 0.0),
+            point(0.0, 1.0, 0.0), point(0.0, 0.0, 1.0)];
+        let center = point(0.25, 0.25, 0.25);
+
+        let mut builder = MeshBuilder::new();
+        for [a, b, c] in [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]] {
+            let (a, b, c) = (&corners[a], &corners[b], &corners[c]);
+            // Each face is wound counter-clockwise seen from outside.
+            let face = vector_math::cross(&(b - a), &(c - a));
+            assert!(vector_math::dot(&face, &(a - &center)) > 0.0);
+            builder.add_triangle([a, b, c],
+                [&normal(a), &normal(b), &normal(c)]);
+        }
+
+        let mesh = Arc::new(builder.build());
+        Group::closed(TriangleMesh::triangles(&mesh).into_iter()
+            .map(|triangle| Arc::new(triangle) as Arc<dyn Shape>)
+            .collect())
+    }
+
+    #[test]
+    fn closed_surface_through_edges_and_corners() {
+        let tetrahedron = tetrahedron(|_| vector(0.0, 0.0, 0.0));
+        let root_2 = 2.0f32.sqrt();
+        let root_3 = 3.0f32.sqrt();
+
+        // In through the edge between the faces in x = 0 and y = 0
+        let intervals = tetrahedron.intervals(&point(-1.0, -1.0, 0.5),
+            &vector(1.0, 1.0, 0.0).normalized()).unwrap();
+        assert_ends(&intervals, &[(root_2, 1.25 * root_2)]);
+
+        // In through the corner at the origin
+        let intervals = tetrahedron.intervals(&point(-1.0, -1.0, -1.0),
+            &vector(1.0, 1.0, 1.0).normalized()).unwrap();
+        assert_ends(&intervals, &[(root_3, 4.0 / 3.0 * root_3)]);
+    }
+
+    #[test]
+    fn closed_surface_uses_face_normals() {
+        // Smooth normals pointing away from the middle, which tip far from
+        // the faces' own normals
+        let center = point(0.25, 0.25, 0.25);
+        let tetrahedron =
+            tetrahedron(|corner| (corner - &center).normalized());
+
+        // In through the face in y = 0 at a shallow angle, near the corner
+        // where the interpolated normal faces along the ray
+        let intervals = tetrahedron.intervals(&point(-0.2, -0.05, 0.1),
+            &vector(1.0, 0.05, 0.0)).unwrap();
+        assert_ends(&intervals, &[(1.0, 1.15 / 1.05)]);
+    }
+}
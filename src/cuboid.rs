@@ -0,0 +1,235 @@
+use crate::vector_math;
+use crate::vector_math::{Point, Vector};
+use crate::shape::{Shape, IntersectResult, BoundingBox, Interval};
+use crate::csg;
+
+
+/// A rectangular box. It's aligned with the world axes unless an orientation
+/// is given.
+pub struct Cuboid {
+    /// Center of the box
+    pub center: Point,
+    /// Half the size of the box along each of its own axes
+    pub half_size: Vector,
+    /// Unit directions of the box's own x, y and z axes, which must be at
+    /// right angles to each other; None for the world axes
+    pub orientation: Option<[Vector; 3]>
+}
+
+impl Cuboid {
+    /// Axis-aligned box between two opposite corners
+    pub fn from_corners(a: &Point, b: &Point) -> Cuboid {
+        let half_size = Vector {
+            dx: (b.x - a.x).abs() / 2.0,
+            dy: (b.y - a.y).abs() / 2.0,
+            dz: (b.z - a.z).abs() / 2.0
+        };
+        Cuboid {
+            center: a + (b - a) * 0.5,
+            half_size,
+            orientation: None
+        }
+    }
+
+    /// Turn the box by `degrees` about an axis through its center.
+    pub fn rotated(self, axis: &Vector, degrees: f32) -> Cuboid {
+        let [x, y, z] = self.axes();
+        Cuboid {
+            orientation: Some([
+                vector_math::rotate(&x, axis, degrees).normalized(),
+                vector_math::rotate(&y, axis, degrees).normalized(),
+                vector_math::rotate(&z, axis, degrees).normalized()]),
+            ..self
+        }
+    }
+
+    fn axes(&self) -> [Vector; 3] {
+        match &self.orientation {
+            Some(axes) => axes.clone(),
+            None => [
+                Vector {dx: 1.0, dy: 0.0, dz: 0.0},
+                Vector {dx: 0.0, dy: 1.0, dz: 0.0},
+                Vector {dx: 0.0, dy: 0.0, dz: 1.0}]
+        }
+    }
+}
+
+impl Shape for Cuboid {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        // Each of the box's axes reaches out along each world axis by its
+        // half size times the size of its component on that world axis.
+        let [x, y, z] = self.axes();
+        let half = [self.half_size.dx, self.half_size.dy, self.half_size.dz];
+        let reach = Vector {
+            dx: x.dx.abs() * half[0] + y.dx.abs() * half[1] +
+                z.dx.abs() * half[2],
+            dy: x.dy.abs() * half[0] + y.dy.abs() * half[1] +
+                z.dy.abs() * half[2],
+            dz: x.dz.abs() * half[0] + y.dz.abs() * half[1] +
+                z.dz.abs() * half[2]
+        };
+
+        Some(BoundingBox {
+            corner: Point {
+                x: self.center.x - reach.dx,
+                y: self.center.y - reach.dy,
+                z: self.center.z - reach.dz
+            },
+            extent: reach * 2.0
+        })
+    }
+
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        Some(csg::convex_intervals(self, src, ray))
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        // The same slab test as BoundingBox::intersect, done in the box's
+        // own coordinates, keeping track of which slab gave the near and far
+        // intersections so the face that was hit is known.
+        let axes = self.axes();
+        let half = [self.half_size.dx, self.half_size.dy, self.half_size.dz];
+        let to_src = src - &self.center;
+
+        let mut largest_near = f32::MIN;
+        let mut near_axis = 0;
+        let mut smallest_far = f32::MAX;
+        let mut far_axis = 0;
+
+        for axis in 0..3 {
+            let start = vector_math::dot(&to_src, &axes[axis]);
+            let direction = vector_math::dot(ray, &axes[axis]);
+
+            if direction == 0.0 {
+                // The ray runs parallel to this slab, so it misses the box
+                // unless it starts inside the slab.
+                if start.abs() > half[axis] {
+                    return None;
+                }
+                continue;
+            }
+
+            let to_min_plane = (-half[axis] - start) / direction;
+            let to_max_plane = (half[axis] - start) / direction;
+            let (slab_near, slab_far) = if to_min_plane < to_max_plane {
+                (to_min_plane, to_max_plane)
+            } else {
+                (to_max_plane, to_min_plane)
+            };
+
+            if slab_near > largest_near {
+                largest_near = slab_near;
+                near_axis = axis;
+            }
+            if slab_far < smallest_far {
+                smallest_far = slab_far;
+                far_axis = axis;
+            }
+        }
+
+        if smallest_far < largest_near || smallest_far < near {
+            return None;
+        }
+
+        // Use the near face unless it's behind the near plane, e.g. when the
+        // ray starts inside the box. The normal points out of the box, so
+        // against the ray on the near face and along it on the far face.
+        let (dist, axis, outward) = if largest_near >= near {
+            (largest_near, near_axis, -1.0)
+        } else {
+            (smallest_far, far_axis, 1.0)
+        };
+        let direction = vector_math::dot(ray, &axes[axis]);
+
+        Some(IntersectResult {
+            normal: &axes[axis] * (outward * direction.signum()),
+            dist
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {x, y, z}
+    }
+
+    fn vector(dx: f32, dy: f32, dz: f32) -> Vector {
+        Vector {dx, dy, dz}
+    }
+
+    fn rotated_box() -> Cuboid {
+        Cuboid::from_corners(&point(0.0, 1.5, 1.0), &point(2.0, 2.5, 5.0))
+            .rotated(&vector(1.0, 1.0, 0.0).normalized(), 35.0)
+    }
+
+    #[test]
+    fn outward_face_normals() {
+        let cuboid = rotated_box();
+        let axes = cuboid.axes();
+        let half = [cuboid.half_size.dx, cuboid.half_size.dy,
+            cuboid.half_size.dz];
+
+        for axis in 0..3 {
+            // A point on the face off its center, and a direction across it
+            let across = &axes[(axis + 1) % 3] *
+                (0.5 * half[(axis + 1) % 3]);
+            for sign in [-1.0, 1.0] {
+                let outward = &axes[axis] * sign;
+                let on_face = &cuboid.center + &(&outward * half[axis]) +
+                    &across;
+
+                // From outside, square on and at an angle, the face hit
+                // first faces back along the ray.
+                let oblique = (&across - &outward * 2.0).normalized();
+                for ray in [-&outward, oblique] {
+                    let src = &on_face + &(&ray * -5.0);
+                    let hit = cuboid.intersect(&src, &ray, 0.0).unwrap();
+                    assert!((hit.dist - 5.0).abs() < 1e-4);
+                    assert!((&hit.normal - &outward).magnitude() < 1e-5,
+                        "{:?} for {:?}", hit.normal, outward);
+                }
+
+                // From inside, the normal of the face the ray leaves by
+                // still points out of the box.
+                let hit = cuboid.intersect(&cuboid.center, &outward, 0.0)
+                    .unwrap();
+                assert!((hit.dist - half[axis]).abs() < 1e-4);
+                assert!((&hit.normal - &outward).magnitude() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn tight_bounding_box() {
+        let cuboid = rotated_box();
+        let axes = cuboid.axes();
+        let bounding_box = cuboid.bounding_box().unwrap();
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for corner in 0..8 {
+            let sign = |bit: usize| if corner & bit == 0 {-1.0} else {1.0};
+            let point = &cuboid.center +
+                &(&axes[0] * (sign(1) * cuboid.half_size.dx)) +
+                &(&axes[1] * (sign(2) * cuboid.half_size.dy)) +
+                &(&axes[2] * (sign(4) * cuboid.half_size.dz));
+            for (i, value) in [point.x, point.y, point.z].iter().enumerate() {
+                min[i] = min[i].min(*value);
+                max[i] = max[i].max(*value);
+            }
+        }
+
+        let corner = &bounding_box.corner;
+        let far = &bounding_box.corner + &bounding_box.extent;
+        let found_min = [corner.x, corner.y, corner.z];
+        let found_max = [far.x, far.y, far.z];
+        for i in 0..3 {
+            assert!((found_min[i] - min[i]).abs() < 1e-5);
+            assert!((found_max[i] - max[i]).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::vector_math;
+use crate::vector_math::{Point, Vector};
+use crate::shape::{Shape, IntersectResult, BoundingBox};
+
+
+/// A flat, round disk facing the direction of `normal`.
+pub struct Disk {
+    /// Center of the disk
+    pub center: Point,
+    /// Direction the disk faces
+    pub normal: Vector,
+    /// Radius of the disk
+    pub radius: f32
+}
+
+/// A flat ring: a disk with a round hole of `inner_radius` in the middle.
+pub struct Annulus {
+    /// Center of the ring
+    pub center: Point,
+    /// Direction the ring faces
+    pub normal: Vector,
+    /// Radius of the hole
+    pub inner_radius: f32,
+    /// Radius of the outside edge
+    pub outer_radius: f32
+}
+
+// Box enclosing a circle of `radius` around `center`, in the plane facing
+// `normal`. Along each world axis, the circle reaches out by its radius
+// times the sine of the angle between that axis and the normal.
+fn circle_bounding_box(center: &Point, normal: &Vector, radius: f32) ->
+        BoundingBox {
+    let normal = normal.normalized();
+    let reach = |component: f32|
+        radius * (1.0 - component * component).max(0.0).sqrt();
+    let reach = Vector {
+        dx: reach(normal.dx),
+        dy: reach(normal.dy),
+        dz: reach(normal.dz)
+    };
+
+    BoundingBox {
+        corner: Point {
+            x: center.x - reach.dx,
+            y: center.y - reach.dy,
+            z: center.z - reach.dz
+        },
+        extent: reach * 2.0
+    }
+}
+
+// Intersect the ray with the plane through `center` facing `normal`, and
+// keep the hit if it's between `inner_radius` and `outer_radius` from the
+// center.
+fn intersect_ring(center: &Point,
+        normal: &Vector,
+        inner_radius: f32,
+        outer_radius: f32,
+        src: &Point,
+        ray: &Vector,
+        near: f32) -> Option<IntersectResult> {
+    let normal = normal.normalized();
+
+    let den = vector_math::dot(ray, &normal);
+    if den.abs() < 0.000001 {
+        // The ray is (very nearly) parallel to the plane.
+        return None;
+    }
+
+    let dist = vector_math::dot(&(center - src), &normal) / den;
+    if dist < near {
+        return None;
+    }
+
+    let from_center = src + ray * dist - center;
+    let distance_squared = vector_math::dot(&from_center, &from_center);
+    if distance_squared > outer_radius * outer_radius ||
+            distance_squared < inner_radius * inner_radius {
+        return None;
+    }
+
+    Some(IntersectResult {
+        normal,
+        dist
+    })
+}
+
+impl Shape for Disk {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(circle_bounding_box(&self.center, &self.normal, self.radius))
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        intersect_ring(&self.center, &self.normal, 0.0, self.radius,
+            src, ray, near)
+    }
+}
+
+impl Shape for Annulus {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(circle_bounding_box(&self.center, &self.normal,
+            self.outer_radius))
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        intersect_ring(&self.center, &self.normal,
+            self.inner_radius, self.outer_radius, src, ray, near)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {x, y, z}
+    }
+
+    fn vector(dx: f32, dy: f32, dz: f32) -> Vector {
+        Vector {dx, dy, dz}
+    }
+
+    const CENTER: Point = Point {x: 1.0, y: -1.0, z: 2.0};
+
+    // Facing a direction that isn't along any axis, and not normalized
+    fn normal() -> Vector {
+        vector(0.0, 1.0, 2.0)
+    }
+
+    // Points in the plane at `radius` from the center, all the way round
+    fn circle(radius: f32) -> impl Iterator<Item = Point> {
+        let normal = normal().normalized();
+        let u = vector_math::cross(&normal, &vector(1.0, 0.0, 0.0))
+            .normalized();
+        let v = vector_math::cross(&normal, &u);
+        (0..36).map(move |step| {
+            let angle = (step as f32 * 10.0).to_radians();
+            &CENTER + &(&u * (radius * angle.cos())) +
+                &(&v * (radius * angle.sin()))
+        })
+    }
+
+    // Whether a ray to `target` from either side hits `shape` there, facing
+    // the same way each time
+    fn hits(shape: &dyn Shape, target: &Point) -> bool {
+        let normal = normal().normalized();
+        let mut hit_count = 0;
+        for src in [point(2.0, 3.0, 6.0), point(-1.0, -4.0, -3.0)] {
+            let to_target = target - &src;
+            let ray = to_target.normalized();
+            if let Some(hit) = shape.intersect(&src, &ray, 0.0) {
+                assert!((hit.dist - to_target.magnitude()).abs() < 1e-4);
+                assert!((&hit.normal - &normal).magnitude() < 1e-5);
+                hit_count += 1;
+            }
+        }
+        assert!(hit_count != 1, "hit from only one side");
+        hit_count == 2
+    }
+
+    // Check that `shape`'s bounding box is the smallest holding a circle of
+    // `radius`.
+    fn check_bounding_box(shape: &dyn Shape, radius: f32) {
+        let bounding_box = shape.bounding_box().unwrap();
+        let far = &bounding_box.corner + &bounding_box.extent;
+        let (mut min, mut max) = (far.clone(), bounding_box.corner.clone());
+        let tolerance = 1e-5;
+        for p in circle(radius) {
+            assert!(p.x >= bounding_box.corner.x - tolerance &&
+                p.x <= far.x + tolerance);
+            assert!(p.y >= bounding_box.corner.y - tolerance &&
+                p.y <= far.y + tolerance);
+            assert!(p.z >= bounding_box.corner.z - tolerance &&
+                p.z <= far.z + tolerance);
+            min = point(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = point(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        // The sampled points come within a few percent of every side.
+        let slack = 0.02 * radius;
+        assert!((&min - &bounding_box.corner).magnitude() < slack);
+        assert!((&max - &far).magnitude() < slack);
+    }
+
+    #[test]
+    fn disk() {
+        let disk = Disk {center: CENTER, normal: normal(), radius: 2.0};
+        assert!(hits(&disk, &CENTER));
+        assert!(circle(1.0).chain(circle(1.99)).all(|p| hits(&disk, &p)));
+        assert!(!circle(2.01).chain(circle(4.0)).any(|p| hits(&disk, &p)));
+        check_bounding_box(&disk, 2.0);
+    }
+
+    #[test]
+    fn annulus() {
+        let annulus = Annulus {
+            center: CENTER,
+            normal: normal(),
+            inner_radius: 1.0,
+            outer_radius: 2.0
+        };
+        assert!(circle(1.01).chain(circle(1.5)).chain(circle(1.99))
+            .all(|p| hits(&annulus, &p)));
+        // The hole and outside the ring
+        assert!(!hits(&annulus, &CENTER));
+        assert!(!circle(0.5).chain(circle(0.99)).chain(circle(2.01))
+            .any(|p| hits(&annulus, &p)));
+        check_bounding_box(&annulus, 2.0);
+    }
+
+    #[test]
+    fn bounding_box_facing_an_axis() {
+        let disk = Disk {
+            center: CENTER,
+            normal: vector(0.0, 0.0, -3.0),
+            radius: 1.5
+        };
+        let bounding_box = disk.bounding_box().unwrap();
+        assert!((&bounding_box.corner - &point(-0.5, -2.5, 2.0)).magnitude() <
+            1e-6);
+        assert!((&bounding_box.extent - &vector(3.0, 3.0, 0.0)).magnitude() <
+            1e-6);
+    }
+}
@@ -0,0 +1,110 @@
+use std::f32::consts::PI;
+
+/// Shapes of reconstruction filter, all of which are zero at the radius.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    /// Equal weight everywhere within the radius
+    Box,
+    /// Weight falls off linearly to zero at the radius
+    Tent,
+    /// Gaussian bell curve, shifted down to reach zero at the radius
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3
+    Mitchell,
+    /// Sinc windowed by a wider sinc, reaching zero at the radius
+    Lanczos
+}
+
+/// A pixel reconstruction filter. Each sample contributes to every pixel
+/// whose center is within `radius` pixels of it (horizontally and
+/// vertically), weighted by the filter.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    /// Shape of the filter
+    pub kind: FilterKind,
+    /// Distance in pixels beyond which samples get no weight, which must be
+    /// positive
+    pub radius: f32
+}
+
+impl Default for Filter {
+    // A box filter covering exactly one pixel, so each sample only
+    // contributes to the pixel it's in.
+    fn default() -> Filter {
+        Filter::with_default_radius(FilterKind::Box)
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Filter {
+    /// Create a filter with the radius commonly used for its kind.
+    pub fn with_default_radius(kind: FilterKind) -> Filter {
+        let radius = match kind {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0
+        };
+        Filter {kind, radius}
+    }
+
+    /// Weight of a sample at offset (`dx`, `dy`) from a pixel center. The
+    /// filters are separable, so this is the product of the weights in each
+    /// dimension. Note the Mitchell and Lanczos filters have negative lobes.
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        let d = d.abs();
+        if d > self.radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - d / self.radius,
+            FilterKind::Gaussian => {
+                const ALPHA: f32 = 2.0;
+                (-ALPHA * d * d).exp() -
+                    (-ALPHA * self.radius * self.radius).exp()
+            },
+            FilterKind::Mitchell => {
+                const B: f32 = 1.0 / 3.0;
+                const C: f32 = 1.0 / 3.0;
+
+                // The cubic is defined over [0, 2]
+                let x = 2.0 * d / self.radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * B - 6.0 * C) * x * x * x +
+                        (-18.0 + 12.0 * B + 6.0 * C) * x * x +
+                        (6.0 - 2.0 * B)) / 6.0
+                } else {
+                    ((-B - 6.0 * C) * x * x * x +
+                        (6.0 * B + 30.0 * C) * x * x +
+                        (-12.0 * B - 48.0 * C) * x +
+                        (8.0 * B + 24.0 * C)) / 6.0
+                }
+            },
+            FilterKind::Lanczos => sinc(d) * sinc(d / self.radius)
+        }
+    }
+
+    /// Range of pixel indices (inclusive) in one dimension whose centers are
+    /// within the filter radius of the sample coordinate `x`. The range is
+    /// half-open in terms of distance, so a box filter of radius 0.5 only
+    /// ever covers the pixel the sample is in.
+    pub fn pixel_range(&self, x: f32) -> (isize, isize) {
+        let first = (x - 0.5 - self.radius).floor() as isize + 1;
+        let last = (x - 0.5 + self.radius).floor() as isize;
+        (first, last)
+    }
+}
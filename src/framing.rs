@@ -0,0 +1,73 @@
+use crate::vector_math::{Point, Vector};
+use crate::shape::BoundingBox;
+use crate::render::View;
+
+// Field of view angle in degrees (vertical) used for automatically framed
+// views
+const FRAMING_ANGLE: f32 = 45.0;
+
+/// Directions to view a scene from when framing it automatically. These
+/// assume +y is up in the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FramingDirection {
+    /// Looking from +z towards -z
+    Front,
+    /// Looking straight down from +y, with -z at the top of the image
+    Top,
+    /// Looking from the +x, +y, +z corner towards the opposite corner
+    Iso
+}
+
+impl FramingDirection {
+    /// Look up a direction by the name used for it on the command line.
+    pub fn from_name(name: &str) -> Option<FramingDirection> {
+        match name {
+            "front" => Some(FramingDirection::Front),
+            "top" => Some(FramingDirection::Top),
+            "iso" => Some(FramingDirection::Iso),
+            _ => None
+        }
+    }
+}
+
+/// Create a view of `width` x `height` pixels that looks at everything in
+/// `bounds` from the given direction. The view is framed around a sphere
+/// enclosing the box, so the whole box is visible whatever its shape.
+pub fn frame(bounds: &BoundingBox,
+        direction: FramingDirection,
+        width: u32,
+        height: u32) -> View {
+    let center = &bounds.corner + &bounds.extent * 0.5;
+
+    // Give empty or single-point scenes some size, so the viewer isn't
+    // placed right on top of them.
+    let mut radius = bounds.extent.magnitude() / 2.0;
+    if radius <= 0.0 {
+        radius = 1.0;
+    }
+
+    let (toward_viewer, up) = match direction {
+        FramingDirection::Front => (
+            Vector {dx: 0.0, dy: 0.0, dz: 1.0},
+            Vector {dx: 0.0, dy: 1.0, dz: 0.0}),
+        FramingDirection::Top => (
+            Vector {dx: 0.0, dy: 1.0, dz: 0.0},
+            Vector {dx: 0.0, dy: 0.0, dz: -1.0}),
+        FramingDirection::Iso => (
+            Vector {dx: 1.0, dy: 1.0, dz: 1.0}.normalized(),
+            Vector {dx: 0.0, dy: 1.0, dz: 0.0})
+    };
+
+    // The sphere has to fit within the narrower of the vertical and
+    // horizontal fields of view.
+    let aspect_ratio = width as f32 / height as f32;
+    let vertical_half_angle = (FRAMING_ANGLE / 2.0).to_radians();
+    let horizontal_half_angle =
+        (vertical_half_angle.tan() * aspect_ratio).atan();
+    let half_angle = vertical_half_angle.min(horizontal_half_angle);
+    let distance = radius / half_angle.sin();
+
+    let from: Point = &center + toward_viewer * distance;
+    View::new(from, center, up, FRAMING_ANGLE, distance * 0.001,
+        width, height)
+}
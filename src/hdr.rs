@@ -0,0 +1,236 @@
+use std::io::Write;
+
+use crate::color::Color;
+use crate::render::RenderTarget;
+
+
+// Convert a color to Radiance's shared-exponent RGBE representation: three
+// 8-bit mantissas and an 8-bit exponent (biased by 128) taken from the
+// brightest channel.
+fn to_rgbe(color: &Color) -> [u8; 4] {
+    let brightest = color.r.max(color.g).max(color.b);
+    if brightest < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // Find the exponent such that brightest = mantissa * 2^exponent, with
+    // the mantissa in [0.5, 1.0).
+    let mut exponent = brightest.log2().floor() as i32 + 1;
+    let mut mantissa = brightest / 2f32.powi(exponent);
+    if mantissa >= 1.0 {
+        mantissa /= 2.0;
+        exponent += 1;
+    } else if mantissa < 0.5 {
+        mantissa *= 2.0;
+        exponent -= 1;
+    }
+
+    let scale = mantissa * 256.0 / brightest;
+    [
+        (color.r.max(0.0) * scale) as u8,
+        (color.g.max(0.0) * scale) as u8,
+        (color.b.max(0.0) * scale) as u8,
+        (exponent + 128).clamp(0, 255) as u8
+    ]
+}
+
+// Run-length encode one channel of a scanline. Runs of identical bytes are
+// written as a count above 128 followed by the byte; anything else is
+// written as a count of up to 128 followed by that many literal bytes.
+fn write_rle_channel(values: &[u8], stream: &mut dyn Write) ->
+        std::io::Result<()> {
+    const MIN_RUN: usize = 4;
+    const MAX_RUN: usize = 127;
+    const MAX_LITERAL: usize = 128;
+
+    let mut start = 0;
+    while start < values.len() {
+        // Measure the run starting here.
+        let mut run = 1;
+        while start + run < values.len() && run < MAX_RUN &&
+                values[start + run] == values[start] {
+            run += 1;
+        }
+
+        if run >= MIN_RUN {
+            stream.write_all(&[128 + run as u8, values[start]])?;
+            start += run;
+        } else {
+            // Collect literals until the next worthwhile run begins.
+            let mut end = start + 1;
+            while end < values.len() && end - start < MAX_LITERAL {
+                let upcoming = &values[end..values.len().min(end + MIN_RUN)];
+                if upcoming.len() == MIN_RUN &&
+                        upcoming.iter().all(|&value| value == upcoming[0]) {
+                    break;
+                }
+                end += 1;
+            }
+            stream.write_all(&[(end - start) as u8])?;
+            stream.write_all(&values[start..end])?;
+            start = end;
+        }
+    }
+    Ok(())
+}
+
+// Write `image` in Radiance RGBE (.hdr) format, keeping the full unclamped
+// range of the colors.
+pub fn write(image: &RenderTarget, stream: &mut dyn Write) ->
+        std::io::Result<()> {
+    writeln!(stream, "#?RADIANCE")?;
+    writeln!(stream, "FORMAT=32-bit_rle_rgbe")?;
+    writeln!(stream)?;
+    writeln!(stream, "-Y {} +X {}", image.height, image.width)?;
+
+    // Run-length encoding is only defined for scanline widths in this range;
+    // other images are written as flat RGBE pixels.
+    let use_rle = image.width >= 8 && image.width <= 0x7fff;
+
+    let mut channels: Vec<Vec<u8>> = (0..4).map(
+        |_| Vec::with_capacity(image.width)).collect();
+    for j in 0..image.height {
+        for channel in channels.iter_mut() {
+            channel.clear();
+        }
+
+        for i in 0..image.width {
+            let rgbe = to_rgbe(image.get(i, j));
+            if use_rle {
+                for (channel, value) in channels.iter_mut().zip(&rgbe) {
+                    channel.push(*value);
+                }
+            } else {
+                stream.write_all(&rgbe)?;
+            }
+        }
+
+        if use_rle {
+            stream.write_all(&[2, 2,
+                (image.width >> 8) as u8, (image.width & 0xff) as u8])?;
+            for channel in &channels {
+                write_rle_channel(channel, stream)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(r: f32, g: f32, b: f32) -> Color {
+        Color {r, g, b}
+    }
+
+    // Expand the run-length encoded channels of a scanline `width` pixels
+    // wide, returning its pixels and the rest of `data`.
+    fn decode_rle(mut data: &[u8], width: usize) -> (Vec<[u8; 4]>, &[u8]) {
+        let mut channels = vec![];
+        for _ in 0..4 {
+            let mut channel = vec![];
+            while channel.len() < width {
+                let count = data[0] as usize;
+                if count > 128 {
+                    channel.resize(channel.len() + count - 128, data[1]);
+                    data = &data[2..];
+                } else {
+                    assert!(count > 0);
+                    channel.extend_from_slice(&data[1..=count]);
+                    data = &data[count + 1..];
+                }
+            }
+            assert_eq!(channel.len(), width);
+            channels.push(channel);
+        }
+        let pixels = (0..width)
+            .map(|i| [channels[0][i], channels[1][i], channels[2][i],
+                channels[3][i]])
+            .collect();
+        (pixels, data)
+    }
+
+    #[test]
+    fn rgbe() {
+        // Each is the brightest channel's mantissa in [128, 256), then the
+        // others on the same scale, and the exponent plus 128.
+        assert_eq!(to_rgbe(&color(0.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(&color(1.0, 1.0, 1.0)), [128, 128, 128, 129]);
+        assert_eq!(to_rgbe(&color(0.5, 0.25, 0.0)), [128, 64, 0, 128]);
+        assert_eq!(to_rgbe(&color(3.0, 1.5, 6.0)), [96, 48, 192, 131]);
+        assert_eq!(to_rgbe(&color(1000.0, 0.0, 250.0)), [250, 0, 62, 138]);
+        // Negative channels can't be represented.
+        assert_eq!(to_rgbe(&color(-1.0, 0.75, 0.0)), [0, 192, 0, 128]);
+    }
+
+    #[test]
+    fn rle_channels() {
+        let encode = |values: &[u8]| {
+            let mut encoded = vec![];
+            write_rle_channel(values, &mut encoded).unwrap();
+            encoded
+        };
+        assert_eq!(encode(&[5, 5, 5, 5, 5, 1, 2, 3]), [133, 5, 3, 1, 2, 3]);
+        assert_eq!(encode(&[1, 2, 9, 9, 9, 9]), [2, 1, 2, 132, 9]);
+        // Runs too short to be worth it are literals.
+        assert_eq!(encode(&[4, 4, 4, 7]), [4, 4, 4, 4, 7]);
+        // Runs and literals longer than a count can hold are split.
+        assert_eq!(encode(&[7; 200]), [255, 7, 201, 7]);
+        let literals: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let encoded = encode(&literals);
+        assert_eq!(encoded.len(), 202);
+        assert_eq!((encoded[0], encoded[129]), (128, 72));
+    }
+
+    // An image `width` pixels wide and 2 high, with long runs on the first
+    // row and none on the second, and its data after the header
+    fn write_image(width: usize) -> (Vec<u8>, RenderTarget) {
+        let mut image = RenderTarget::new(width, 2);
+        for i in 0..width {
+            image.set(i, 0, color(1.0, 0.5, 0.0));
+            image.set(i, 1, color(i as f32 / 4.0, 0.0, 2.0));
+        }
+
+        let mut encoded = vec![];
+        write(&image, &mut encoded).unwrap();
+        let header = format!(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X {}\n", width);
+        assert!(encoded.starts_with(header.as_bytes()));
+        (encoded[header.len()..].to_vec(), image)
+    }
+
+    #[test]
+    fn rle_scanlines() {
+        for &width in &[8, 13, 0x7fff] {
+            let (data, image) = write_image(width);
+            let mut rest = &data[..];
+            for j in 0..2 {
+                assert_eq!(rest[..4],
+                    [2, 2, (width >> 8) as u8, (width & 0xff) as u8]);
+                let (pixels, after) = decode_rle(&rest[4..], width);
+                for (i, pixel) in pixels.iter().enumerate() {
+                    assert_eq!(*pixel, to_rgbe(image.get(i, j)));
+                }
+                rest = after;
+            }
+            assert!(rest.is_empty());
+        }
+
+        // The row of identical pixels is a single run in each channel.
+        let (data, _) = write_image(8);
+        assert_eq!(data[4..12], [136, 128, 136, 64, 136, 0, 136, 129]);
+    }
+
+    #[test]
+    fn flat_scanlines() {
+        for &width in &[1, 7, 0x8000] {
+            let (data, image) = write_image(width);
+            assert_eq!(data.len(), width * 2 * 4);
+            for (index, pixel) in data.chunks(4).enumerate() {
+                let (i, j) = (index % width, index / width);
+                assert_eq!(pixel, to_rgbe(image.get(i, j)));
+            }
+        }
+    }
+}
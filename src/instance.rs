@@ -0,0 +1,261 @@
+use std::sync::Arc;
+use crate::vector_math;
+use crate::vector_math::{Point, Vector, Transform};
+use crate::shape::{Shape, IntersectResult, BoundingBox, Interval};
+use crate::space_partition::{PartitionTree, PartitionItem};
+use crate::csg::{self, Crossing};
+
+
+/// A copy of a shared shape, moved, turned or scaled by `transform`. Any
+/// number of instances can refer to the same shape, so a model that's
+/// repeated around a scene only has to be stored once.
+pub struct Instance {
+    object: Arc<dyn Shape>,
+    // From the object's own space to the world
+    transform: Transform,
+    // From the world back to the object's own space
+    to_object: Transform,
+    bounding_box: Option<BoundingBox>
+}
+
+impl Instance {
+    /// Place `object` in the world by way of `transform`.
+    pub fn new(object: Arc<dyn Shape>, transform: Transform) -> Instance {
+        let bounding_box = object.bounding_box()
+            .map(|bounding_box| bounding_box.transformed(&transform));
+        Instance {
+            object,
+            to_object: transform.inverse(),
+            transform,
+            bounding_box
+        }
+    }
+
+    // Bring a hit on the object back into the world. The ray wasn't
+    // normalized on the way in, so the distance is the same in both spaces.
+    fn to_world(&self, result: IntersectResult) -> IntersectResult {
+        // The infinitely distant ends of unbounded solids have no normal.
+        if !result.dist.is_finite() {
+            return result;
+        }
+
+        IntersectResult {
+            normal: self.transform.apply_normal(&result.normal).normalized(),
+            dist: result.dist
+        }
+    }
+}
+
+impl Shape for Instance {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.bounding_box.clone()
+    }
+
+    fn is_composite(&self) -> bool {
+        self.object.is_composite()
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        // The ray is taken into the object's space without normalizing its
+        // direction, so that distances along it are the same in both spaces.
+        let object_src = self.to_object.apply_point(src);
+        let object_ray = self.to_object.apply_vector(ray);
+        let result = self.object.intersect(&object_src, &object_ray, near)?;
+        Some(self.to_world(result))
+    }
+
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        let object_src = self.to_object.apply_point(src);
+        let object_ray = self.to_object.apply_vector(ray);
+        let intervals = self.object.intervals(&object_src, &object_ray)?;
+
+        Some(intervals.into_iter()
+            .map(|interval| Interval {
+                enter: self.to_world(interval.enter),
+                exit: self.to_world(interval.exit)
+            })
+            .collect())
+    }
+}
+
+/// Several shapes treated as one, typically so that a whole model can be
+/// shared between instances. The shapes get a space partition of their own,
+/// so a ray that reaches the group only tests the shapes near its path.
+///
+/// A group of solid shapes is solid wherever any of them are; a group with
+/// any shapes that are only surfaces isn't a solid at all. A closed group is
+/// instead made of pieces of a surface, such as the triangles of a mesh,
+/// which together enclose a solid with the surface normals facing out.
+pub struct Group {
+    tree: PartitionTree<Arc<dyn Shape>>,
+    closed: bool,
+    solid: bool
+}
+
+impl Group {
+    /// Group of shapes, which is a solid if they all are
+    pub fn new(shapes: Vec<Arc<dyn Shape>>) -> Group {
+        let solid = shapes.iter().all(|shape| csg::is_solid(shape.as_ref()));
+        Group {
+            tree: PartitionTree::from_items(shapes.into_iter()),
+            closed: false,
+            solid
+        }
+    }
+
+    /// Group of surface pieces that together enclose a solid
+    pub fn closed(pieces: Vec<Arc<dyn Shape>>) -> Group {
+        Group {
+            tree: PartitionTree::from_items(pieces.into_iter()),
+            closed: true,
+            solid: true
+        }
+    }
+}
+
+impl Shape for Group {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.tree.bounding_box()
+    }
+
+    fn is_composite(&self) -> bool {
+        true
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        self.tree.intersect(src, ray, near, f32::INFINITY, None)
+            .map(|(normal, dist, _)| IntersectResult {
+                normal,
+                dist
+            })
+    }
+
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        if !self.solid {
+            return None;
+        }
+
+        let mut crossings = vec![];
+        for item in self.tree.items_along(src, ray) {
+            let shape = item.shape();
+            if self.closed {
+                // Each piece of a closed surface is crossed at most once,
+                // going in if the ray meets it from the outside. Shading
+                // normals can tip the other way near the silhouette, so
+                // that's decided by the piece's own plane.
+                if let Some(hit) = shape.intersect(src, ray,
+                        f32::NEG_INFINITY) {
+                    let entering = vector_math::dot(
+                        &shape.face_normal(&hit), ray) < 0.0;
+                    crossings.push(Crossing {hit, entering});
+                }
+            } else if let Some(intervals) = shape.intervals(src, ray) {
+                crossings.extend(csg::crossings_of(intervals));
+            }
+        }
+        Some(if self.closed {
+            csg::closed_surface(crossings)
+        } else {
+            csg::union(crossings)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {x, y, z}
+    }
+
+    fn vector(dx: f32, dy: f32, dz: f32) -> Vector {
+        Vector {dx, dy, dz}
+    }
+
+    // A unit sphere stretched to twice the size along x, then turned about
+    // z and moved, making an ellipsoid centered on (5, 1, 0).
+    fn ellipsoid() -> (Instance, Transform) {
+        let transform = Transform::scaling(&vector(2.0, 1.0, 1.0))
+            .then(&Transform::rotation(&vector(0.0, 0.0, 1.0), 30.0))
+            .then(&Transform::translation(&vector(5.0, 1.0, 0.0)));
+        let sphere = Sphere {center: Point::origin(), radius: 1.0};
+        (Instance::new(Arc::new(sphere), transform.clone()), transform)
+    }
+
+    #[test]
+    fn hits_in_world_space() {
+        let (instance, transform) = ellipsoid();
+        let to_object = transform.inverse();
+
+        let src = point(0.0, -2.0, 0.5);
+        let rays = [vector(1.0, 0.6, 0.0), vector(5.0, 3.0, -0.5),
+            vector(1.0, 0.8, 0.0)];
+        for ray in rays.iter().map(Vector::normalized) {
+            let hit = instance.intersect(&src, &ray, 0.0).unwrap();
+            let world_hit = &src + &(&ray * hit.dist);
+
+            // The world space hit is on the ellipsoid, ...
+            let object_hit = to_object.apply_point(&world_hit);
+            let on_sphere = object_hit - Point::origin();
+            assert!((on_sphere.magnitude() - 1.0).abs() < 1e-4);
+
+            // ... with the unit normal of the transformed surface, which
+            // faces the ray.
+            assert!((hit.normal.magnitude() - 1.0).abs() < 1e-5);
+            let expected = transform.apply_normal(&on_sphere).normalized();
+            assert!((&hit.normal - &expected).magnitude() < 1e-4);
+            assert!(hit.normal.dot(&ray) < 0.0);
+
+            // The distances are in world space.
+            let intervals = instance.intervals(&src, &ray).unwrap();
+            assert_eq!(intervals.len(), 1);
+            assert!((intervals[0].enter.dist - hit.dist).abs() < 1e-4);
+            let exit = &src + &(&ray * intervals[0].exit.dist);
+            let object_exit = to_object.apply_point(&exit) - Point::origin();
+            assert!((object_exit.magnitude() - 1.0).abs() < 1e-4);
+        }
+
+        // Along the long axis, the ellipsoid is 4 across.
+        let axis = transform.apply_vector(&vector(1.0, 0.0, 0.0)).normalized();
+        let src = point(5.0, 1.0, 0.0) + &axis * -10.0;
+        let intervals = instance.intervals(&src, &axis).unwrap();
+        assert!((intervals[0].enter.dist - 8.0).abs() < 1e-4);
+        assert!((intervals[0].exit.dist - 12.0).abs() < 1e-4);
+        assert!((&intervals[0].enter.normal + &axis).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn bounding_box_encloses_child() {
+        let (instance, transform) = ellipsoid();
+        let bounding_box = instance.bounding_box().unwrap();
+        let child = Sphere {center: Point::origin(), radius: 1.0}
+            .bounding_box().unwrap();
+
+        let far = &bounding_box.corner + &bounding_box.extent;
+        for &(i, j, k) in &[(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0),
+                (0.0, 0.0, 1.0), (1.0, 1.0, 0.0), (1.0, 0.0, 1.0),
+                (0.0, 1.0, 1.0), (1.0, 1.0, 1.0)] {
+            let corner = transform.apply_point(&point(
+                child.corner.x + i * child.extent.dx,
+                child.corner.y + j * child.extent.dy,
+                child.corner.z + k * child.extent.dz));
+            let tolerance = 1e-4;
+            assert!(corner.x >= bounding_box.corner.x - tolerance &&
+                corner.x <= far.x + tolerance);
+            assert!(corner.y >= bounding_box.corner.y - tolerance &&
+                corner.y <= far.y + tolerance);
+            assert!(corner.z >= bounding_box.corner.z - tolerance &&
+                corner.z <= far.z + tolerance);
+        }
+
+        // It's no bigger than it needs to be to hold the turned box.
+        let (cos, sin) = (30f32.to_radians().cos(), 30f32.to_radians().sin());
+        let expected = vector(2.0 * (2.0 * cos + sin), 2.0 * (2.0 * sin + cos),
+            2.0);
+        assert!((&bounding_box.extent - &expected).magnitude() < 1e-4);
+    }
+}
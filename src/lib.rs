@@ -0,0 +1,106 @@
+//! A Whitted-style ray tracer for scenes described in NFF.
+//!
+//! Scenes can either be read from NFF with [`nff::read`], or built up in
+//! code by adding shapes, surfaces and lights to a [`Scene`]. A [`View`]
+//! describes the camera, and [`render`] traces the scene into a
+//! [`RenderTarget`], from which the pixel colors can be read back or written
+//! out with [`output::write`]. Nothing is printed while rendering; set
+//! [`RenderOptions::progress`] to follow along.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use rust_raytracer::{Color, Light, Phong, Point, RenderOptions,
+//!     RenderTarget, Scene, Sphere, Vector, View};
+//!
+//! let mut scene = Scene::new();
+//! scene.background = Color::black();
+//! scene.add_light(Light {
+//!     position: Point {x: -4.0, y: 4.0, z: 4.0},
+//!     color: Color::white()
+//! });
+//! scene.add_primitive(
+//!     Box::new(Sphere {center: Point::origin(), radius: 1.0}),
+//!     Arc::new(Phong {
+//!         color: Color {r: 1.0, g: 0.0, b: 0.0},
+//!         diffuse_component: 0.8,
+//!         specular_component: 0.2,
+//!         shine: 10.0,
+//!         reflectance: 0.0,
+//!         transmittance: 0.0,
+//!         refraction_index: 1.0
+//!     }));
+//!
+//! let view = View::new(
+//!     Point {x: 0.0, y: 0.0, z: 5.0},
+//!     Point::origin(),
+//!     Vector {dx: 0.0, dy: 1.0, dz: 0.0},
+//!     45.0, 0.1, 32, 32);
+//!
+//! let mut target = RenderTarget::new(32, 32);
+//! let options = RenderOptions::default();
+//! rust_raytracer::render(&view, &scene, &mut target, &options);
+//!
+//! assert!(target.get(16, 16).r > 0.0);
+//! ```
+
+#![warn(missing_docs)]
+
+mod vector_math;
+mod color;
+mod shape;
+mod polygon;
+mod mesh;
+mod sphere;
+mod plane;
+mod cuboid;
+mod torus;
+mod quadric;
+mod cone;
+mod disk;
+mod instance;
+mod csg;
+pub mod nff;
+mod render;
+mod camera;
+mod stereo;
+mod framing;
+mod ppm;
+mod png;
+mod pfm;
+mod hdr;
+pub mod output;
+mod tone_map;
+mod phong;
+mod blinn_phong;
+mod scene;
+mod space_partition;
+mod sampling;
+mod filter;
+
+pub use crate::color::Color;
+pub use crate::vector_math::{Point, Vector, PointNormal, Axis, Transform};
+pub use crate::shape::{Shape, BoundingBox, IntersectResult, Interval};
+pub use crate::sphere::Sphere;
+pub use crate::plane::Plane;
+pub use crate::cuboid::Cuboid;
+pub use crate::torus::Torus;
+pub use crate::quadric::Quadric;
+pub use crate::cone::Cone;
+pub use crate::disk::{Disk, Annulus};
+pub use crate::polygon::Polygon;
+pub use crate::mesh::{TriangleMesh, MeshFace, MeshBuilder, Triangle};
+pub use crate::instance::{Instance, Group};
+pub use crate::csg::{Csg, Operation};
+pub use crate::phong::Phong;
+pub use crate::blinn_phong::BlinnPhong;
+pub use crate::render::{render, View, Surface, Primitive, RenderOptions,
+    RenderTarget, Crop, AdaptiveSampling, Progress};
+pub use crate::camera::Projection;
+pub use crate::sampling::SamplePattern;
+pub use crate::filter::{Filter, FilterKind};
+pub use crate::stereo::{Stereo, StereoLayout};
+pub use crate::framing::{frame, FramingDirection};
+pub use crate::png::BitDepth;
+pub use crate::tone_map::{ToneMapping, Operator, Transfer};
+pub use crate::scene::{Scene, Light};
+pub use crate::space_partition::SpacePartition;
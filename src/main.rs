@@ -1,26 +1,120 @@
 use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
 
 extern crate clap;
 use clap::{App, Arg, ArgGroup};
 
-mod vector_math;
-mod color;
-mod shape;
-mod polygon;
-mod sphere;
-mod cone;
-mod nff;
-mod render;
-mod ppm;
-mod phong;
-mod blinn_phong;
-mod scene;
-mod space_partition;
-
-
-fn main() -> Result<(), Box<dyn Error>> {
+use rust_raytracer::{nff, output, render, frame, RenderOptions, RenderTarget,
+    Crop, AdaptiveSampling, Progress, SamplePattern, Filter, FilterKind,
+    Projection, Stereo, StereoLayout, FramingDirection, BoundingBox, BitDepth,
+    Operator, ToneMapping, Transfer};
+use rust_raytracer::output::{OutputFormat, OutputOptions};
+
+// Most times --adaptive-depth can let a pixel be subdivided
+const MAX_ADAPTIVE_DEPTH: u32 = 8;
+
+
+fn main() {
+    // Report errors with their Display text rather than the Debug output
+    // that returning them from main would give.
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
     let matches = App::new("Ray Tracer")
-        .about("Generates PPM images using NFF commands read from stdin")
+        .about("Generates images from NFF scene descriptions")
+        .arg(Arg::with_name("input")
+            .value_name("INPUT")
+            .help("NFF file to render, or - for stdin (default)"))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("FILE")
+            .default_value("trace.ppm")
+            .help("Image file to write, or - for stdout; the format is \
+                chosen by the file extension (.ppm, .png, .pfm, .hdr)"))
+        .arg(Arg::with_name("bit-depth")
+            .long("bit-depth")
+            .value_name("BITS")
+            .possible_values(&["8", "16"])
+            .default_value("8")
+            .help("Bits per channel for PNG output"))
+        .arg(Arg::with_name("alpha")
+            .long("alpha")
+            .help("Write an alpha channel for PNG output, making the \
+                background transparent"))
+        .arg(Arg::with_name("exposure")
+            .long("exposure")
+            .value_name("STOPS")
+            .default_value("0")
+            .allow_hyphen_values(true)
+            .help("Exposure adjustment in stops for PPM/PNG output"))
+        .arg(Arg::with_name("tone-map")
+            .long("tone-map")
+            .value_name("OPERATOR")
+            .possible_values(&["clip", "reinhard", "filmic", "aces"])
+            .default_value("clip")
+            .help("Tone mapping operator for PPM/PNG output"))
+        .arg(Arg::with_name("srgb")
+            .long("srgb")
+            .help("Apply the sRGB transfer curve to PPM/PNG output"))
+        .arg(Arg::with_name("projection")
+            .long("projection")
+            .value_name("PROJECTION")
+            .possible_values(&["perspective", "orthographic", "fisheye",
+                "equirectangular", "cube-map"])
+            .help("Camera projection, overriding the one in the scene \
+                (default: perspective)"))
+        .arg(Arg::with_name("auto-frame")
+            .long("auto-frame")
+            .value_name("DIRECTION")
+            .possible_values(&["front", "top", "iso"])
+            .help("Place the camera automatically to frame the whole scene \
+                from the given direction, with +y up, replacing any view \
+                in the scene"))
+        .arg(Arg::with_name("resolution")
+            .long("resolution")
+            .value_name("WxH")
+            .validator(|value| match parse_resolution(&value) {
+                Some(_) => Ok(()),
+                None => Err(String::from("expected WIDTHxHEIGHT"))
+            })
+            .help("Image size, overriding the scene's resolution while \
+                keeping its vertical field of view (default for \
+                automatically framed views: 512x512)"))
+        .arg(Arg::with_name("crop")
+            .long("crop")
+            .value_name("X,Y,W,H")
+            .validator(|value| match parse_crop(&value) {
+                Some(_) => Ok(()),
+                None => Err(String::from("expected X,Y,WIDTH,HEIGHT"))
+            })
+            .help("Only render the given rectangle of the image, in pixels \
+                from the top left corner, leaving the rest black and \
+                transparent"))
+        .arg(Arg::with_name("stereo")
+            .long("stereo")
+            .value_name("LAYOUT")
+            .possible_values(&["side-by-side", "over-under", "anaglyph"])
+            .help("Render a stereo pair, combined with the given layout"))
+        .arg(Arg::with_name("eye-separation")
+            .long("eye-separation")
+            .value_name("DISTANCE")
+            .requires("stereo")
+            .help("Distance between the eyes for stereo rendering \
+                (default: 1/30 of the convergence distance)"))
+        .arg(Arg::with_name("convergence")
+            .long("convergence")
+            .value_name("DISTANCE")
+            .requires("stereo")
+            .help("Distance at which the eyes' lines of sight cross for \
+                stereo rendering (default: the distance to the view's at \
+                point)"))
         .arg(Arg::with_name("phong")
             .long("phong")
             .help("Use Phong shading (default)"))
@@ -29,18 +123,322 @@ fn main() -> Result<(), Box<dyn Error>> {
             .help("Use Blinn-Phong shading"))
         .group(ArgGroup::with_name("shading")
             .args(&["phong", "blinn-phong"]))
+        .arg(Arg::with_name("threads")
+            .long("threads")
+            .value_name("N")
+            .validator(|value| match parse_count(&value) {
+                Some(_) => Ok(()),
+                None => Err(String::from("expected a positive whole number"))
+            })
+            .help("Number of rendering threads (default: one per CPU)"))
+        .arg(Arg::with_name("samples")
+            .long("samples")
+            .value_name("N")
+            .default_value("1")
+            .validator(|value| match parse_count(&value) {
+                Some(_) => Ok(()),
+                None => Err(String::from("expected a positive whole number"))
+            })
+            .help("Number of rays traced per pixel for anti-aliasing"))
+        .arg(Arg::with_name("sample-pattern")
+            .long("sample-pattern")
+            .value_name("PATTERN")
+            .possible_values(&["grid", "jittered", "rotated-grid"])
+            .default_value("grid")
+            .help("Arrangement of the rays within each pixel"))
+        .arg(Arg::with_name("seed")
+            .long("seed")
+            .value_name("SEED")
+            .default_value("0")
+            .validator(|value| match value.parse::<u64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(String::from("expected a whole number"))
+            })
+            .help("Random seed for jittered sampling"))
+        .arg(Arg::with_name("adaptive")
+            .long("adaptive")
+            .help("Sample adaptively, subdividing pixels where the color \
+                changes sharply (overrides --samples)"))
+        .arg(Arg::with_name("adaptive-threshold")
+            .long("adaptive-threshold")
+            .value_name("DIFFERENCE")
+            .default_value("0.1")
+            .validator(|value| match parse_threshold(&value) {
+                Some(_) => Ok(()),
+                None => Err(String::from("expected a number of at least 0"))
+            })
+            .help("Color difference that triggers adaptive subdivision"))
+        .arg(Arg::with_name("adaptive-depth")
+            .long("adaptive-depth")
+            .value_name("N")
+            .default_value("2")
+            .validator(|value| match parse_adaptive_depth(&value) {
+                Some(_) => Ok(()),
+                None => Err(format!("expected a whole number from 0 to {}",
+                    MAX_ADAPTIVE_DEPTH))
+            })
+            .help("Maximum number of adaptive subdivisions per pixel, at \
+                most 8; each one can quadruple the rays traced"))
+        .arg(Arg::with_name("filter")
+            .long("filter")
+            .value_name("FILTER")
+            .possible_values(
+                &["box", "tent", "gaussian", "mitchell", "lanczos"])
+            .default_value("box")
+            .help("Pixel reconstruction filter"))
+        .arg(Arg::with_name("filter-radius")
+            .long("filter-radius")
+            .value_name("PIXELS")
+            .validator(|value| match parse_radius(&value) {
+                Some(_) => Ok(()),
+                None => Err(String::from("expected a positive number"))
+            })
+            .help("Radius of the reconstruction filter (default depends on \
+                the filter)"))
+        .arg(Arg::with_name("sample-map")
+            .long("sample-map")
+            .value_name("FILE")
+            .help("Also write an image showing the number of rays traced \
+                for each pixel"))
         .get_matches();
 
     let use_phong = !matches.is_present("blinn-phong");
 
-    let (view, scene) = nff::read(use_phong)?;
+    let mut options = RenderOptions {
+        progress: Some(Box::new(print_progress)),
+        ..RenderOptions::default()
+    };
+    if let Some(threads) = matches.value_of("threads").and_then(parse_count) {
+        options.threads = threads;
+    }
+    options.samples =
+        matches.value_of("samples").and_then(parse_count).unwrap_or(1);
+    options.pattern = match matches.value_of("sample-pattern") {
+        Some("jittered") => SamplePattern::Jittered,
+        Some("rotated-grid") => SamplePattern::RotatedGrid,
+        _ => SamplePattern::Grid
+    };
+    options.seed = matches.value_of("seed")
+        .and_then(|value| value.parse().ok()).unwrap_or(0);
+    options.filter = Filter::with_default_radius(
+        match matches.value_of("filter") {
+            Some("tent") => FilterKind::Tent,
+            Some("gaussian") => FilterKind::Gaussian,
+            Some("mitchell") => FilterKind::Mitchell,
+            Some("lanczos") => FilterKind::Lanczos,
+            _ => FilterKind::Box
+        });
+    if let Some(radius) =
+            matches.value_of("filter-radius").and_then(parse_radius) {
+        options.filter.radius = radius;
+    }
+    options.crop = matches.value_of("crop").and_then(parse_crop);
+    if matches.is_present("adaptive") {
+        options.adaptive = Some(AdaptiveSampling {
+            threshold: matches.value_of("adaptive-threshold")
+                .and_then(parse_threshold).unwrap_or(0.1),
+            max_depth: matches.value_of("adaptive-depth")
+                .and_then(parse_adaptive_depth).unwrap_or(2)
+        });
+    }
+
+    let stereo = match matches.value_of("stereo") {
+        Some(layout) => Some(Stereo {
+            layout: match layout {
+                "over-under" => StereoLayout::OverUnder,
+                "anaglyph" => StereoLayout::Anaglyph,
+                _ => StereoLayout::SideBySide
+            },
+            eye_separation: match matches.value_of("eye-separation") {
+                Some(separation) => Some(separation.parse()?),
+                None => None
+            },
+            convergence: match matches.value_of("convergence") {
+                Some(convergence) => Some(convergence.parse()?),
+                None => None
+            }
+        }),
+        None => None
+    };
+
+    // Check the output format before rendering, so a bad file name doesn't
+    // waste a long render.
+    let output_path = matches.value_of("output").unwrap_or("trace.ppm");
+    let format = OutputFormat::from_path(output_path)?;
+    let sample_map = match matches.value_of("sample-map") {
+        Some(path) => Some((path, OutputFormat::from_path(path)?)),
+        None => None
+    };
+
+    let output_options = OutputOptions {
+        bit_depth: match matches.value_of("bit-depth") {
+            Some("16") => BitDepth::Sixteen,
+            _ => BitDepth::Eight
+        },
+        alpha: matches.is_present("alpha"),
+        tone_mapping: ToneMapping {
+            exposure: matches.value_of("exposure").unwrap_or("0").parse()?,
+            operator: match matches.value_of("tone-map") {
+                Some("reinhard") => Operator::Reinhard,
+                Some("filmic") => Operator::Filmic,
+                Some("aces") => Operator::Aces,
+                _ => Operator::Clip
+            },
+            transfer: if matches.is_present("srgb") {
+                Transfer::Srgb
+            } else {
+                Transfer::Linear
+            }
+        }
+    };
+
+    let mut warn = |message: &str| eprintln!("{}", message);
+    let (view, scene) = match matches.value_of("input").unwrap_or("-") {
+        "-" => nff::read_scene_with_warnings(
+            &mut io::stdin().lock(), use_phong, &mut warn)?,
+        input_path => {
+            let mut input = BufReader::new(File::open(input_path)?);
+            nff::read_scene_with_warnings(&mut input, use_phong, &mut warn)?
+        }
+    };
+
+    let resolution = matches.value_of("resolution").and_then(parse_resolution);
+    let auto_frame =
+        matches.value_of("auto-frame").and_then(FramingDirection::from_name);
+    let mut view = match (auto_frame, view) {
+        (Some(direction), view) => {
+            let (width, height) = resolution
+                .or_else(|| view.map(|view| (view.width, view.height)))
+                .unwrap_or((512, 512));
+            let bounds = scene.bounding_box().unwrap_or_else(BoundingBox::zero);
+            frame(&bounds, direction, width, height)
+        },
+        (None, Some(mut view)) => {
+            if let Some((width, height)) = resolution {
+                view.set_resolution(width, height);
+            }
+            view
+        },
+        (None, None) => {
+            return Err("the scene has no view; use --auto-frame to place \
+                the camera automatically".into());
+        }
+    };
+
+    if let Some(projection) =
+            matches.value_of("projection").and_then(Projection::from_name) {
+        view.projection = projection;
+    }
+
+    let target = match stereo {
+        Some(stereo) => {
+            let (width, height) = stereo.image_size(&view);
+            let mut target = RenderTarget::new(width, height);
+            stereo.render(&view, &scene, &mut target, &options);
+            target
+        },
+        None => {
+            let mut target = RenderTarget::new(
+                view.width as usize, view.height as usize);
+            render(&view, &scene, &mut target, &options);
+            target
+        }
+    };
+
+    write_image(&target, output_path, format, &output_options)?;
+
+    if let Some((path, format)) = sample_map {
+        write_image(&target.sample_count_image(), path, format,
+            &OutputOptions::default())?;
+    }
 
-    let mut target = render::RenderTarget::new(
-        view.width as usize, view.height as usize);
+    Ok(())
+}
+
+// Report the progress of a render on stderr.
+fn print_progress(progress: Progress) {
+    match progress {
+        Progress::BuildingSpacePartition => {
+            eprintln!("Building space partition");
+        },
+        Progress::Tile {index, count} => {
+            eprintln!("Rendering tile {} of {}", index + 1, count);
+        },
+        Progress::LeftEye => eprintln!("Rendering left eye"),
+        Progress::RightEye => eprintln!("Rendering right eye")
+    }
+}
+
+// Parse an image size given as WIDTHxHEIGHT.
+fn parse_resolution(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => {
+            Some((width, height))
+        },
+        _ => None
+    }
+}
+
+// Parse a rectangle given as X,Y,WIDTH,HEIGHT.
+fn parse_crop(value: &str) -> Option<Crop> {
+    let values = value.split(',')
+        .map(|word| word.trim().parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    match values[..] {
+        [x, y, width, height] => Some(Crop {x, y, width, height}),
+        _ => None
+    }
+}
+
+// Parse a number of threads or samples, of which there has to be at least
+// one.
+fn parse_count(value: &str) -> Option<usize> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Some(count),
+        _ => None
+    }
+}
 
-    render::render(&view, &scene, &mut target);
+// Parse the color difference that triggers adaptive subdivision.
+fn parse_threshold(value: &str) -> Option<f32> {
+    match value.parse::<f32>() {
+        Ok(threshold) if threshold >= 0.0 && threshold.is_finite() => {
+            Some(threshold)
+        },
+        _ => None
+    }
+}
 
-    ppm::write(&target, "trace.ppm")?;
+// Parse a filter radius, which has to be positive for any samples to count.
+fn parse_radius(value: &str) -> Option<f32> {
+    match value.parse::<f32>() {
+        Ok(radius) if radius > 0.0 && radius.is_finite() => Some(radius),
+        _ => None
+    }
+}
+
+// Parse the number of times adaptive sampling can subdivide a pixel. Each
+// level can split every region into four, so deep ones take practically
+// forever.
+fn parse_adaptive_depth(value: &str) -> Option<u32> {
+    match value.parse::<u32>() {
+        Ok(depth) if depth <= MAX_ADAPTIVE_DEPTH => Some(depth),
+        _ => None
+    }
+}
 
+// Write `image` to the file at `path`, or stdout if `path` is "-".
+fn write_image(image: &RenderTarget,
+        path: &str,
+        format: OutputFormat,
+        options: &OutputOptions) -> Result<(), Box<dyn Error>> {
+    let mut stream: Box<dyn Write> = match path {
+        "-" => Box::new(BufWriter::new(io::stdout().lock())),
+        _ => Box::new(BufWriter::new(File::create(path)?))
+    };
+    output::write(image, format, options, &mut stream)?;
+    stream.flush()?;
     Ok(())
 }
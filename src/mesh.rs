@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::vector_math;
+use crate::vector_math::{Point, Vector};
+use crate::shape::{Shape, IntersectResult, BoundingBox};
+use crate::space_partition::{PartitionTree, PartitionItem};
+
+
+/// A triangle in a mesh, as indices into the mesh's points and normals for
+/// each of its corners.
+#[derive(Debug, Clone)]
+pub struct MeshFace {
+    /// Indices into the mesh's points
+    pub points: [u32; 3],
+    /// Indices into the mesh's normals
+    pub normals: [u32; 3]
+}
+
+/// A set of triangles sharing a buffer of points and normals, so vertices
+/// used by several triangles are only stored once.
+#[derive(Debug, Clone, Default)]
+pub struct TriangleMesh {
+    /// Corner positions shared between triangles
+    pub points: Vec<Point>,
+    /// Corner normals shared between triangles
+    pub normals: Vec<Vector>,
+    /// The triangles
+    pub faces: Vec<MeshFace>
+}
+
+impl TriangleMesh {
+    /// Mesh with no triangles
+    pub fn new() -> TriangleMesh {
+        TriangleMesh::default()
+    }
+
+    /// Split the mesh into one shape per triangle, so the space partition
+    /// can sort them individually. The shapes all share the mesh.
+    pub fn triangles(mesh: &Arc<TriangleMesh>) -> Vec<Triangle> {
+        (0..mesh.faces.len()).map(|index| Triangle {
+            mesh: mesh.clone(),
+            index
+        }).collect()
+    }
+}
+
+/// Builds up a mesh one triangle at a time, merging identical points and
+/// normals.
+#[derive(Default)]
+pub struct MeshBuilder {
+    mesh: TriangleMesh,
+    point_indices: HashMap<[u32; 3], u32>,
+    normal_indices: HashMap<[u32; 3], u32>
+}
+
+impl MeshBuilder {
+    /// Builder with no triangles yet
+    pub fn new() -> MeshBuilder {
+        MeshBuilder::default()
+    }
+
+    /// Whether no triangles have been added
+    pub fn is_empty(&self) -> bool {
+        self.mesh.faces.is_empty()
+    }
+
+    /// Add a triangle with the given corners and the normals at each of
+    /// them; the points should be counter-clockwise when viewed from the
+    /// side the normals face.
+    pub fn add_triangle(&mut self, points: [&Point; 3], normals: [&Vector; 3]) {
+        let mut face = MeshFace {points: [0; 3], normals: [0; 3]};
+        for corner in 0..3 {
+            face.points[corner] = self.add_point(points[corner]);
+            face.normals[corner] = self.add_normal(normals[corner]);
+        }
+        self.mesh.faces.push(face);
+    }
+
+    fn add_point(&mut self, point: &Point) -> u32 {
+        let key = [point.x.to_bits(), point.y.to_bits(), point.z.to_bits()];
+        let points = &mut self.mesh.points;
+        *self.point_indices.entry(key).or_insert_with(|| {
+            points.push(point.clone());
+            (points.len() - 1) as u32
+        })
+    }
+
+    fn add_normal(&mut self, normal: &Vector) -> u32 {
+        let key = [normal.dx.to_bits(), normal.dy.to_bits(),
+            normal.dz.to_bits()];
+        let normals = &mut self.mesh.normals;
+        *self.normal_indices.entry(key).or_insert_with(|| {
+            normals.push(normal.clone());
+            (normals.len() - 1) as u32
+        })
+    }
+
+    /// Finish the mesh, releasing the memory used to find shared vertices.
+    pub fn build(mut self) -> TriangleMesh {
+        self.mesh.points.shrink_to_fit();
+        self.mesh.normals.shrink_to_fit();
+        self.mesh.faces.shrink_to_fit();
+        self.mesh
+    }
+}
+
+/// A single triangle of a mesh.
+#[derive(Clone)]
+pub struct Triangle {
+    /// Mesh the triangle belongs to
+    pub mesh: Arc<TriangleMesh>,
+    /// Index of the triangle in the mesh's faces
+    pub index: usize
+}
+
+// A whole mesh as one shape, with its triangles sorted into a space
+// partition of their own. This saves a primitive per triangle in the scene,
+// and rays leaving one triangle can hit the others, as in a group.
+pub(crate) struct MeshShape {
+    tree: PartitionTree<Triangle>
+}
+
+impl MeshShape {
+    pub(crate) fn new(mesh: TriangleMesh) -> MeshShape {
+        MeshShape {
+            tree: PartitionTree::from_items(
+                TriangleMesh::triangles(&Arc::new(mesh)).into_iter())
+        }
+    }
+}
+
+impl PartitionItem for Triangle {
+    fn shape(&self) -> &dyn Shape {
+        self
+    }
+}
+
+impl Shape for MeshShape {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.tree.bounding_box()
+    }
+
+    fn is_composite(&self) -> bool {
+        true
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        self.tree.intersect(src, ray, near, f32::INFINITY, None)
+            .map(|(normal, dist, _)| IntersectResult {normal, dist})
+    }
+}
+
+fn components(point: &Point) -> [f32; 3] {
+    [point.x, point.y, point.z]
+}
+
+impl Triangle {
+    fn face(&self) -> &MeshFace {
+        &self.mesh.faces[self.index]
+    }
+
+    fn point(&self, corner: usize) -> &Point {
+        &self.mesh.points[self.face().points[corner] as usize]
+    }
+
+    fn normal(&self, corner: usize) -> &Vector {
+        &self.mesh.normals[self.face().normals[corner] as usize]
+    }
+}
+
+impl Shape for Triangle {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let (a, b, c) = (self.point(0), self.point(1), self.point(2));
+        let min_x = a.x.min(b.x).min(c.x);
+        let min_y = a.y.min(b.y).min(c.y);
+        let min_z = a.z.min(b.z).min(c.z);
+
+        Some(BoundingBox {
+            corner: Point {
+                x: min_x,
+                y: min_y,
+                z: min_z
+            },
+            extent: Vector {
+                dx: a.x.max(b.x).max(c.x) - min_x,
+                dy: a.y.max(b.y).max(c.y) - min_y,
+                dz: a.z.max(b.z).max(c.z) - min_z
+            }
+        })
+    }
+
+    // Watertight ray/triangle intersection, after Woop, Benthin and Wald,
+    // "Watertight Ray/Triangle Intersection" (JCGT, 2013). The triangle is
+    // transformed into a space where the ray runs along +z from the origin,
+    // and the 2D edge functions are evaluated there. Rays through an edge or
+    // vertex shared by neighboring triangles hit at least one of them, so
+    // there are no cracks in the mesh.
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        let direction = [ray.dx, ray.dy, ray.dz];
+
+        // Make the largest component of the ray direction the z axis, and
+        // keep the winding of the triangle by swapping the other two if
+        // that component is negative.
+        let kz = if ray.dx.abs() > ray.dy.abs() {
+            if ray.dx.abs() > ray.dz.abs() {0} else {2}
+        } else if ray.dy.abs() > ray.dz.abs() {
+            1
+        } else {
+            2
+        };
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        if direction[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+
+        // Shear constants that align the ray with +z
+        let shear_x = direction[kx] / direction[kz];
+        let shear_y = direction[ky] / direction[kz];
+        let shear_z = 1.0 / direction[kz];
+
+        let origin = components(src);
+        let relative = |corner: usize| {
+            let point = components(self.point(corner));
+            [point[0] - origin[0], point[1] - origin[1], point[2] - origin[2]]
+        };
+        let (a, b, c) = (relative(0), relative(1), relative(2));
+
+        let ax = a[kx] - shear_x * a[kz];
+        let ay = a[ky] - shear_y * a[kz];
+        let bx = b[kx] - shear_x * b[kz];
+        let by = b[ky] - shear_y * b[kz];
+        let cx = c[kx] - shear_x * c[kz];
+        let cy = c[ky] - shear_y * c[kz];
+
+        // Scaled barycentric coordinates of the hit point
+        let mut u = cx * by - cy * bx;
+        let mut v = ax * cy - ay * cx;
+        let mut w = bx * ay - by * ax;
+
+        // Exactly zero edge functions are ambiguous in single precision, so
+        // recompute them in double precision to settle which side of the
+        // edge the ray is on.
+        if u == 0.0 || v == 0.0 || w == 0.0 {
+            u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+            v = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+            w = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+        }
+
+        // The triangle is two-sided, so the ray hits it if the edge
+        // functions all agree in sign, either way.
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+
+        let determinant = u + v + w;
+        if determinant == 0.0 {
+            // The ray is in the plane of the triangle.
+            return None;
+        }
+
+        let az = shear_z * a[kz];
+        let bz = shear_z * b[kz];
+        let cz = shear_z * c[kz];
+        let dist = (u * az + v * bz + w * cz) / determinant;
+        if dist < near {
+            return None;
+        }
+
+        let normal = (self.normal(0) * (u / determinant) +
+            self.normal(1) * (v / determinant) +
+            self.normal(2) * (w / determinant)).normalized();
+
+        Some(IntersectResult {
+            normal,
+            dist
+        })
+    }
+
+    fn face_normal(&self, hit: &IntersectResult) -> Vector {
+        let (a, b, c) = (self.point(0), self.point(1), self.point(2));
+        let normal = vector_math::cross(&(b - a), &(c - a)).normalized();
+        if vector_math::dot(&normal, &hit.normal) < 0.0 {
+            -&normal
+        } else {
+            normal
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {x, y, z}
+    }
+
+    fn vector(dx: f32, dy: f32, dz: f32) -> Vector {
+        Vector {dx, dy, dz}
+    }
+
+    // Two triangles sharing the edge from `points[0]` to `points[1]`, with
+    // every normal `normal`
+    fn pair(points: [Point; 4], normal: &Vector) -> TriangleMesh {
+        let mut builder = MeshBuilder::new();
+        builder.add_triangle([&points[0], &points[1], &points[2]],
+            [normal, normal, normal]);
+        builder.add_triangle([&points[1], &points[0], &points[3]],
+            [normal, normal, normal]);
+        builder.build()
+    }
+
+    // Whether a ray at `target` from each of a few sources hits at least one
+    // of `triangles` there, and the mesh they make just once
+    fn hits_once(triangles: &[Triangle], target: &Point) -> bool {
+        let shape = MeshShape::new((*triangles[0].mesh).clone());
+        let sources = [point(0.3, 0.4, 5.0), point(-2.0, 3.0, -4.0),
+            point(0.5, 0.5, 0.5)];
+        sources.iter().all(|src| {
+            let to_target = target - src;
+            let expected = to_target.magnitude();
+            let ray = to_target.normalized();
+
+            let mut hits = triangles.iter()
+                .filter_map(|triangle| triangle.intersect(src, &ray, 0.0));
+            let close = |hit: IntersectResult| {
+                (hit.dist - expected).abs() < 1e-4
+            };
+            let first = match hits.next() {
+                Some(hit) => close(hit),
+                None => false
+            };
+            // The mesh as a whole is hit there, and not again beyond it.
+            first && hits.all(close) &&
+                shape.intersect(src, &ray, 0.0).is_some_and(close) &&
+                shape.intersect(src, &ray, expected + 1e-3).is_none()
+        })
+    }
+
+    #[test]
+    fn shared_edges_and_vertices() {
+        // A skewed pair, so the edge isn't aligned with anything
+        let points = [point(0.1, 0.2, 0.3), point(0.9, 0.7, -0.2),
+            point(0.8, -0.3, 0.1), point(0.2, 1.1, 0.2)];
+        let mesh = Arc::new(pair(points.clone(), &vector(0.0, 0.0, 1.0)));
+        let triangles = TriangleMesh::triangles(&mesh);
+        for step in 1..100 {
+            let t = step as f32 / 100.0;
+            let target = &points[0] + &((&points[1] - &points[0]) * t);
+            assert!(hits_once(&triangles, &target), "fell through at {}", t);
+        }
+
+        // A fan of triangles all around a vertex, and along each spoke
+        let center = point(0.4, 0.3, 0.2);
+        let ring = [point(1.1, 0.2, 0.3), point(0.7, 0.9, 0.1),
+            point(-0.1, 0.8, 0.4), point(-0.2, -0.1, 0.2),
+            point(0.5, -0.6, 0.0)];
+        let normal = vector(0.0, 0.0, 1.0);
+        let mut builder = MeshBuilder::new();
+        for (i, corner) in ring.iter().enumerate() {
+            builder.add_triangle([&center, corner, &ring[(i + 1) % ring.len()]],
+                [&normal, &normal, &normal]);
+        }
+        let triangles = TriangleMesh::triangles(&Arc::new(builder.build()));
+        assert!(hits_once(&triangles, &center));
+        for corner in &ring {
+            for step in 1..20 {
+                let t = step as f32 / 20.0;
+                let target = &center + &((corner - &center) * t);
+                assert!(hits_once(&triangles, &target));
+            }
+        }
+    }
+
+    #[test]
+    fn hit_from_behind() {
+        let normal = vector(0.0, 0.0, 1.0);
+        let mut builder = MeshBuilder::new();
+        builder.add_triangle(
+            [&point(0.0, 0.0, 0.0), &point(1.0, 0.0, 0.0),
+                &point(0.0, 1.0, 0.0)],
+            [&normal, &normal, &normal]);
+        let mesh = Arc::new(builder.build());
+        let triangle = &TriangleMesh::triangles(&mesh)[0];
+
+        for (src, ray) in [(point(0.2, 0.3, 2.0), vector(0.0, 0.0, -1.0)),
+                (point(0.2, 0.3, -2.0), vector(0.0, 0.0, 1.0)),
+                (point(-1.0, 0.3, -1.0), vector(1.0, 0.0, 1.0).normalized())] {
+            let hit = triangle.intersect(&src, &ray, 0.0).unwrap();
+            let hit_point = &src + &(&ray * hit.dist);
+            assert!(hit_point.z.abs() < 1e-5);
+            // The shading normal is the same from either side.
+            assert!((&hit.normal - &normal).magnitude() < 1e-5);
+            assert!((&triangle.face_normal(&hit) - &normal).magnitude() <
+                1e-5);
+        }
+
+        // It's missed beside the triangle and behind the ray.
+        let ray = vector(0.0, 0.0, 1.0);
+        assert!(triangle.intersect(&point(0.6, 0.6, -2.0), &ray, 0.0)
+            .is_none());
+        assert!(triangle.intersect(&point(0.2, 0.3, 2.0), &ray, 0.0)
+            .is_none());
+    }
+
+    #[test]
+    fn builder_merges_vertices() {
+        let points = [point(0.0, 0.0, 0.0), point(1.0, 0.0, 0.0),
+            point(0.0, 1.0, 0.0), point(1.0, 1.0, 0.0)];
+        let mesh = pair([points[1].clone(), points[2].clone(),
+            points[0].clone(), points[3].clone()], &vector(0.0, 0.0, 1.0));
+
+        assert_eq!(mesh.points.len(), 4);
+        assert_eq!(mesh.normals.len(), 1);
+        assert_eq!(mesh.faces.len(), 2);
+        // The shared edge uses the same indices in both triangles.
+        let (first, second) = (&mesh.faces[0].points, &mesh.faces[1].points);
+        assert_eq!(first[0], second[1]);
+        assert_eq!(first[1], second[0]);
+        assert_ne!(first[2], second[2]);
+        assert_eq!(mesh.faces[1].normals, [0, 0, 0]);
+
+        // Points that are only nearly the same stay apart.
+        let mut builder = MeshBuilder::new();
+        let normal = vector(0.0, 0.0, 1.0);
+        builder.add_triangle([&points[0], &points[1], &points[2]],
+            [&normal, &normal, &normal]);
+        builder.add_triangle([&points[3], &points[2],
+                &point(1.0 + f32::EPSILON, 0.0, 0.0)],
+            [&normal, &normal, &normal]);
+        let mesh = builder.build();
+        assert_eq!(mesh.points.len(), 5);
+        assert_eq!(mesh.faces[1].points[1], mesh.faces[0].points[2]);
+    }
+}
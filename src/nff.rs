@@ -1,19 +1,34 @@
+//! Reading scenes in the Neutral File Format (NFF), with the extensions
+//! described in the README.
+
 use std::fmt;
+use std::collections::HashMap;
 use std::str::FromStr;
-use std::io;
+use std::io::BufRead;
 use std::error::Error;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::vector_math::Vector;
 use crate::vector_math::Point;
 use crate::vector_math::PointNormal;
 use crate::vector_math;
+use crate::vector_math::Transform;
 use crate::polygon::Polygon;
+use crate::mesh::{MeshBuilder, MeshShape, TriangleMesh};
 use crate::sphere::Sphere;
+use crate::plane::Plane;
+use crate::cuboid::Cuboid;
+use crate::torus::Torus;
+use crate::disk::{Disk, Annulus};
+use crate::quadric::Quadric;
+use crate::shape::{Shape, BoundingBox};
+use crate::instance::{Instance, Group};
+use crate::csg::{self, Csg, Operation};
 use crate::cone::Cone;
 use crate::color::Color;
 use crate::render::View;
 use crate::render::Surface;
+use crate::camera::Projection;
 use crate::phong::Phong;
 use crate::blinn_phong::BlinnPhong;
 use crate::scene::Scene;
@@ -53,8 +68,8 @@ fn parse_values<T>(line: &str, start_word: usize, count: usize) ->
         
     let words = line.split_whitespace().collect::<Vec<_>>();
     if words.len() != start_word + count {
-        // TO DO: Define an error type for this
-        panic!("wrong value count");
+        let command = words.first().copied().unwrap_or_default();
+        return Err(Box::new(NFFError::new(command, "wrong value count")));
     }
 
     let mut values = Vec::<T>::new();
@@ -77,21 +92,21 @@ fn parse_values<T>(line: &str, start_word: usize, count: usize) ->
 fn parse_background(args: &[&str]) -> Result<Color, NFFError> {
     let r = match args[0].parse() {
         Ok(value) => value,
-        Err(e) => {
+        Err(_) => {
             return Err(NFFError::new("b", "invalid red value"));
         }
     };
 
     let g = match args[1].parse() {
         Ok(value) => value,
-        Err(e) => {
+        Err(_) => {
             return Err(NFFError::new("b", "invalid green value"));
         }
     };
 
     let b = match args[2].parse() {
         Ok(value) => value,
-        Err(e) => {
+        Err(_) => {
             return Err(NFFError::new("b", "invalid blue value"));
         }
     };
@@ -99,7 +114,47 @@ fn parse_background(args: &[&str]) -> Result<Color, NFFError> {
     Ok(Color {r, g, b})
 }
 
-fn parse_view(stream: &mut std::io::Stdin) -> Result<View, Box<dyn Error>> {
+// Apply a view setting that isn't part of standard NFF. These can appear
+// anywhere in the view block, or on the lines following it:
+//      aperture <radius>   - lens radius for depth of field
+//      focus <distance>    - distance to the plane in focus
+//      yon <distance>      - far-clip distance
+//      roll <degrees>      - clockwise rotation about the view direction
+//      projection <name>   - perspective, orthographic, fisheye,
+//                            equirectangular or cube-map
+// Returns false if the line isn't a recognized view extension.
+fn parse_view_extension(line: &str, view: &mut View) ->
+        Result<bool, Box<dyn Error>> {
+    if line.starts_with("aperture") {
+        let values = parse_values(line, 1, 1)?;
+        view.aperture = values[0];
+    } else if line.starts_with("focus") {
+        let values = parse_values(line, 1, 1)?;
+        view.focal_distance = Some(values[0]);
+    } else if line.starts_with("yon") {
+        let values = parse_values(line, 1, 1)?;
+        view.yon = Some(values[0]);
+    } else if line.starts_with("roll") {
+        let values = parse_values(line, 1, 1)?;
+        view.roll = values[0];
+    } else if line.starts_with("projection") {
+        let values = parse_values::<String>(line, 1, 1)?;
+        view.projection = match Projection::from_name(&values[0]) {
+            Some(projection) => projection,
+            None => {
+                return Err(Box::new(
+                    NFFError::new("projection", "unknown projection")));
+            }
+        };
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+fn parse_view(stream: &mut dyn BufRead, warn: &mut dyn FnMut(&str)) ->
+        Result<View, Box<dyn Error>> {
+    let mut extensions: Vec<String> = vec![];
     let mut from: Option<Point> = None;
     let mut at: Option<Point> = None;
     let mut up: Option<Vector> = None;
@@ -115,6 +170,11 @@ fn parse_view(stream: &mut std::io::Stdin) -> Result<View, Box<dyn Error>> {
             return Err(Box::new(NFFError::new("v", "missing parameters")));
         }
 
+        // Comments and blank lines are skipped here as anywhere else.
+        if line.starts_with("#") || line.trim().is_empty() {
+            continue;
+        }
+
         if line.starts_with("from") {
             let values = parse_values(&line, 1, 3)?;
             from = Some(Point {x: values[0], y: values[1], z: values[2]});
@@ -139,25 +199,30 @@ fn parse_view(stream: &mut std::io::Stdin) -> Result<View, Box<dyn Error>> {
             let values = parse_values(&line, 1, 2)?;
             res = Some((values[0], values[1]));
         }
+        else {
+            // Possibly an extension; these are applied once the view is
+            // complete.
+            extensions.push(line);
+        }
 
         if let (Some(from), Some(at), Some(up),
                 Some(angle), Some(hither), Some(res)) =
                     (&from, &at, &up, angle, hither, res) {
-            return Ok(View {
-                from: from.clone(),
-                at: at.clone(),
-                up: up.clone(),
-                angle: angle,
-                hither: hither,
-                width: res.0,
-                height: res.1
-            });
+            let mut view = View::new(from.clone(), at.clone(), up.clone(),
+                angle, hither, res.0, res.1);
+            for line in &extensions {
+                if !parse_view_extension(line, &mut view)? {
+                    warn(&format!("unrecognized view parameter: {}",
+                        line.trim_end()));
+                }
+            }
+            return Ok(view);
         }
     }
 }
 
-fn parse_polygon_patch(args: &[&str], stream: &mut std::io::Stdin) ->
-        Result<Polygon, Box<dyn Error>> {
+fn parse_polygon_patch(args: &[&str], stream: &mut dyn BufRead) ->
+        Result<Vec<PointNormal>, Box<dyn Error>> {
     let vertex_count = args[0].parse::<u32>()?;
     if vertex_count < 3 {
         return Err(Box::new(NFFError::new("pp", "insufficient vertex count")));
@@ -177,15 +242,13 @@ fn parse_polygon_patch(args: &[&str], stream: &mut std::io::Stdin) ->
         let mut normal = Vector {dx: values[3], dy: values[4], dz: values[5]};
         normal.normalize();
 
-        vertices.push(PointNormal {point: point, normal: normal});
+        vertices.push(PointNormal {point, normal});
     }
 
-    Ok(Polygon {
-        vertices: vertices
-    })
+    Ok(vertices)
 }
 
-fn parse_cone(stream: &mut std::io::Stdin) ->
+fn parse_cone(stream: &mut dyn BufRead) ->
         Result<Cone, Box<dyn Error>> {
     let mut base_line = String::new();
     stream.read_line(&mut base_line)?;
@@ -195,12 +258,11 @@ fn parse_cone(stream: &mut std::io::Stdin) ->
     stream.read_line(&mut apex_line)?;
     let apex_values = parse_values(&apex_line, 0, 4)?;
 
-    Ok(Cone {
-        base: Point {x: base_values[0], y: base_values[1], z: base_values[2]},
-        apex: Point {x: apex_values[0], y: apex_values[1], z: apex_values[2]},
-        base_radius: base_values[3],
-        apex_radius: apex_values[3]
-    })
+    Ok(Cone::new(
+        Point {x: base_values[0], y: base_values[1], z: base_values[2]},
+        Point {x: apex_values[0], y: apex_values[1], z: apex_values[2]},
+        base_values[3],
+        apex_values[3]))
 }
 
 fn parse_cone_one_line(args: &[&str]) ->
@@ -215,16 +277,15 @@ fn parse_cone_one_line(args: &[&str]) ->
     let az = args[6].parse()?;
     let ar = args[7].parse()?;
 
-    Ok(Cone {
-        base: Point {x: bx, y: by, z: bz},
-        apex: Point {x: ax, y: ay, z: az},
-        base_radius: br,
-        apex_radius: ar
-    })
+    Ok(Cone::new(
+        Point {x: bx, y: by, z: bz},
+        Point {x: ax, y: ay, z: az},
+        br,
+        ar))
 }
 
-fn parse_polygon(args: &[&str], stream: &mut std::io::Stdin) ->
-        Result<Polygon, Box<dyn Error>> {
+fn parse_polygon(args: &[&str], stream: &mut dyn BufRead) ->
+        Result<Vec<PointNormal>, Box<dyn Error>> {
     let vertex_count = args[0].parse::<u32>()?;
     if vertex_count < 3 {
         return Err(Box::new(NFFError::new("p", "insufficient vertex count")));
@@ -254,18 +315,16 @@ fn parse_polygon(args: &[&str], stream: &mut std::io::Stdin) ->
     let mut vertices = Vec::<PointNormal>::new();
     for point in points {
         vertices.push(PointNormal {
-            point: point,
+            point,
             normal: normal.clone()
         })
     }
 
-    Ok(Polygon {
-        vertices: vertices
-    })
+    Ok(vertices)
 }
 
 fn parse_fill(use_phong: bool, args: &[&str]) ->
-        Result<Rc<dyn Surface>, Box<dyn Error>> {
+        Result<Arc<dyn Surface>, Box<dyn Error>> {
     let r = args[0].parse()?;
     let g = args[1].parse()?;
     let b = args[2].parse()?;
@@ -284,24 +343,24 @@ fn parse_fill(use_phong: bool, args: &[&str]) ->
     // type and feed the shading choice into render::render()?
 
     if use_phong {
-        Ok(Rc::new(Phong {
+        Ok(Arc::new(Phong {
             color: Color {r, g, b},
             diffuse_component: kd,
             specular_component: ks,
-            shine: shine,
+            shine,
             reflectance: ks,
-            transmittance: transmittance,
-            refraction_index: refraction_index
+            transmittance,
+            refraction_index
         }))
     } else {
-        Ok(Rc::new(BlinnPhong {
+        Ok(Arc::new(BlinnPhong {
             color: Color {r, g, b},
             diffuse_component: kd,
             specular_component: ks,
-            shine: shine,
+            shine,
             reflectance: ks,
-            transmittance: transmittance,
-            refraction_index: refraction_index
+            transmittance,
+            refraction_index
         }))
     }
 }
@@ -341,25 +400,272 @@ fn parse_sphere(args: &[&str]) -> Result<Sphere, Box<dyn Error>> {
 
     Ok(Sphere {
         center: Point {x, y, z},
-        radius: radius
+        radius
+    })
+}
+
+// Plane extension: "plane x y z nx ny nz", a point on the plane followed by
+// its normal
+fn parse_plane(args: &[&str]) -> Result<Plane, Box<dyn Error>> {
+    let x = args[0].parse()?;
+    let y = args[1].parse()?;
+    let z = args[2].parse()?;
+
+    let dx = args[3].parse()?;
+    let dy = args[4].parse()?;
+    let dz = args[5].parse()?;
+
+    let normal = Vector {dx, dy, dz};
+    if normal.magnitude() == 0.0 {
+        return Err(Box::new(NFFError::new("plane", "zero-length normal")));
+    }
+
+    Ok(Plane {
+        point: Point {x, y, z},
+        normal
+    })
+}
+
+// Box extension: "box x1 y1 z1 x2 y2 z2", two opposite corners of an
+// axis-aligned box, optionally followed by "ax ay az degrees" to turn the
+// box about an axis through its center
+fn parse_box(args: &[&str]) -> Result<Cuboid, Box<dyn Error>> {
+    let mut values = Vec::<f32>::new();
+    for arg in args {
+        values.push(arg.parse()?);
+    }
+
+    let cuboid = Cuboid::from_corners(
+        &Point {x: values[0], y: values[1], z: values[2]},
+        &Point {x: values[3], y: values[4], z: values[5]});
+
+    if values.len() == 10 {
+        let axis = Vector {dx: values[6], dy: values[7], dz: values[8]};
+        if axis.magnitude() == 0.0 {
+            return Err(Box::new(NFFError::new("box", "zero-length axis")));
+        }
+        Ok(cuboid.rotated(&axis, values[9]))
+    } else {
+        Ok(cuboid)
+    }
+}
+
+// Torus extension: "torus x y z ax ay az major minor", the center, the axis
+// the ring goes around and the two radii
+fn parse_torus(args: &[&str]) -> Result<Torus, Box<dyn Error>> {
+    let x = args[0].parse()?;
+    let y = args[1].parse()?;
+    let z = args[2].parse()?;
+
+    let dx = args[3].parse()?;
+    let dy = args[4].parse()?;
+    let dz = args[5].parse()?;
+
+    let major_radius = args[6].parse()?;
+    let minor_radius = args[7].parse()?;
+
+    let axis = Vector {dx, dy, dz};
+    if axis.magnitude() == 0.0 {
+        return Err(Box::new(NFFError::new("torus", "zero-length axis")));
+    }
+
+    Ok(Torus {
+        center: Point {x, y, z},
+        axis,
+        major_radius,
+        minor_radius
+    })
+}
+
+// Disk extension: "disk x y z nx ny nz radius", the center, the direction
+// the disk faces and its radius
+fn parse_disk(args: &[&str]) -> Result<Disk, Box<dyn Error>> {
+    let x = args[0].parse()?;
+    let y = args[1].parse()?;
+    let z = args[2].parse()?;
+
+    let dx = args[3].parse()?;
+    let dy = args[4].parse()?;
+    let dz = args[5].parse()?;
+
+    let radius = args[6].parse()?;
+
+    let normal = Vector {dx, dy, dz};
+    if normal.magnitude() == 0.0 {
+        return Err(Box::new(NFFError::new("disk", "zero-length normal")));
+    }
+
+    Ok(Disk {
+        center: Point {x, y, z},
+        normal,
+        radius
     })
 }
 
-pub fn read(use_phong: bool) -> Result<(View, Scene), Box<dyn Error>> {
+// Annulus extension: "annulus x y z nx ny nz inner outer", like a disk but
+// with inner and outer radii
+fn parse_annulus(args: &[&str]) -> Result<Annulus, Box<dyn Error>> {
+    let x = args[0].parse()?;
+    let y = args[1].parse()?;
+    let z = args[2].parse()?;
+
+    let dx = args[3].parse()?;
+    let dy = args[4].parse()?;
+    let dz = args[5].parse()?;
+
+    let inner_radius = args[6].parse()?;
+    let outer_radius = args[7].parse()?;
+
+    let normal = Vector {dx, dy, dz};
+    if normal.magnitude() == 0.0 {
+        return Err(Box::new(NFFError::new("annulus", "zero-length normal")));
+    }
+
+    Ok(Annulus {
+        center: Point {x, y, z},
+        normal,
+        inner_radius,
+        outer_radius
+    })
+}
+
+// Quadric extensions. The standard shapes are given by "<shape> x y z rx ry
+// rz", the center (or for paraboloids the tip) and radii as described for
+// the Quadric constructors, and general quadrics by "quadric a b c d e f g
+// h i j" for the surface
+//  a x^2 + b y^2 + c z^2 + d xy + e xz + f yz + g x + h y + i z + j = 0
+// Either can be followed by "x1 y1 z1 x2 y2 z2", opposite corners of a box
+// to clip the surface to.
+fn parse_quadric(command: &str, args: &[&str]) ->
+        Result<Quadric, Box<dyn Error>> {
+    let mut values = Vec::<f32>::new();
+    for arg in args {
+        values.push(arg.parse()?);
+    }
+
+    let (quadric, clip_values) = if command == "quadric" {
+        let v = &values;
+        let quadric = Quadric {
+            matrix: [
+                [v[0], v[3] / 2.0, v[4] / 2.0, v[6] / 2.0],
+                [v[3] / 2.0, v[1], v[5] / 2.0, v[7] / 2.0],
+                [v[4] / 2.0, v[5] / 2.0, v[2], v[8] / 2.0],
+                [v[6] / 2.0, v[7] / 2.0, v[8] / 2.0, v[9]]],
+            clip: None
+        };
+        (quadric, &values[10..])
+    } else {
+        let center = Point {x: values[0], y: values[1], z: values[2]};
+        let radii = Vector {dx: values[3], dy: values[4], dz: values[5]};
+        if radii.dx == 0.0 || radii.dy == 0.0 || radii.dz == 0.0 {
+            return Err(Box::new(NFFError::new(command, "zero radius")));
+        }
+
+        let quadric = match command {
+            "ellipsoid" => Quadric::ellipsoid(&center, &radii),
+            "paraboloid" => Quadric::elliptic_paraboloid(&center, &radii),
+            "hyperboloid" => Quadric::hyperboloid(&center, &radii),
+            _ => Quadric::two_sheet_hyperboloid(&center, &radii)
+        };
+        (quadric, &values[6..])
+    };
+
+    if clip_values.is_empty() {
+        return Ok(quadric);
+    }
+
+    let a = Point {x: clip_values[0], y: clip_values[1], z: clip_values[2]};
+    let b = Point {x: clip_values[3], y: clip_values[4], z: clip_values[5]};
+    let corner = Point {x: a.x.min(b.x), y: a.y.min(b.y), z: a.z.min(b.z)};
+    let extent = Vector {
+        dx: (b.x - a.x).abs(),
+        dy: (b.y - a.y).abs(),
+        dz: (b.z - a.z).abs()
+    };
+    Ok(quadric.clipped(BoundingBox {corner, extent}))
+}
+
+// Transform extensions: "translate dx dy dz", "rotate ax ay az degrees" for
+// a turn about the axis (ax, ay, az), and "scale s" or "scale sx sy sz"
+fn parse_transform(command: &str, args: &[&str]) ->
+        Result<Transform, Box<dyn Error>> {
+    let mut values = Vec::<f32>::new();
+    for arg in args {
+        values.push(arg.parse()?);
+    }
+
+    match command {
+        "translate" => Ok(Transform::translation(
+            &Vector {dx: values[0], dy: values[1], dz: values[2]})),
+        "rotate" => {
+            let axis = Vector {dx: values[0], dy: values[1], dz: values[2]};
+            if axis.magnitude() == 0.0 {
+                return Err(Box::new(
+                    NFFError::new(command, "zero-length axis")));
+            }
+            Ok(Transform::rotation(&axis, values[3]))
+        },
+        _ => {
+            let factors = match values[..] {
+                [factor] => Vector {dx: factor, dy: factor, dz: factor},
+                _ => Vector {dx: values[0], dy: values[1], dz: values[2]}
+            };
+            if factors.dx == 0.0 || factors.dy == 0.0 || factors.dz == 0.0 {
+                return Err(Box::new(NFFError::new(command, "zero scale")));
+            }
+            Ok(Transform::scaling(&factors))
+        }
+    }
+}
+
+// CSG extension: "csg union", "csg intersection" or "csg difference" starts
+// a block of solids to combine, which goes on until "end"
+fn parse_operation(args: &[&str]) -> Result<Operation, NFFError> {
+    match args[0] {
+        "union" => Ok(Operation::Union),
+        "intersection" => Ok(Operation::Intersection),
+        "difference" => Ok(Operation::Difference),
+        _ => Err(NFFError::new("csg", "unknown operation"))
+    }
+}
+
+// Commands that can go in a csg block: solids, polygons making up closed
+// surfaces, instances, transforms, and blocks
+const CSG_COMMANDS: [&str; 19] = ["s", "plane", "box", "cc", "torus",
+    "ellipsoid", "paraboloid", "hyperboloid", "hyperboloid2", "quadric", "p",
+    "pp", "instance", "translate", "rotate", "scale", "begin", "end", "csg"];
+
+/// Read a view and scene from a stream of NFF commands. It's an error for
+/// the view to be missing. If `use_phong` is set, surfaces are shaded with
+/// the Phong model, otherwise with Blinn-Phong.
+pub fn read(stream: &mut dyn BufRead, use_phong: bool) ->
+        Result<(View, Scene), Box<dyn Error>> {
+    match read_scene(stream, use_phong)? {
+        (Some(view), scene) => {
+            Ok((view, scene))
+        },
+        (None, _) => {
+            Err(Box::new(NFFError::new("v", "missing view")))
+        }
+    }
+}
+
+/// Like `read`, but allow the view to be missing, e.g. for a scene that will
+/// be framed automatically.
+pub fn read_scene(stream: &mut dyn BufRead, use_phong: bool) ->
+        Result<(Option<View>, Scene), Box<dyn Error>> {
+    read_scene_with_warnings(stream, use_phong, &mut |_| {})
+}
+
+/// Like `read_scene`, but pass `warn` a message about each line that's
+/// skipped because it isn't understood.
+pub fn read_scene_with_warnings(stream: &mut dyn BufRead,
+        use_phong: bool,
+        warn: &mut dyn FnMut(&str)) ->
+        Result<(Option<View>, Scene), Box<dyn Error>> {
     let mut view: Option<View> = None;
-    let mut scene = Scene::new();
-
-    let mut surface: Rc<dyn Surface> = Rc::new(Phong {
-        color: Color {r: 1.0, g: 1.0, b: 1.0},
-        diffuse_component: 1.0,
-        specular_component: 0.0,
-        shine: 1.0,
-        reflectance: 0.0,
-        transmittance: 0.0,
-        refraction_index: 1.0
-    });
-
-    let mut stream = io::stdin();
+    let mut builder = SceneBuilder::new();
+
     loop {
         let mut line = String::new();
         let byte_count = stream.read_line(&mut line)?;
@@ -378,44 +684,562 @@ pub fn read(use_phong: bool) -> Result<(View, Scene), Box<dyn Error>> {
 
         let command = tokens[0];
         let args = &tokens[1..];
-        if command == "v" && args.len() == 0 {
-            view = Some(parse_view(&mut stream)?);
+        if !builder.csg.is_empty() && !CSG_COMMANDS.contains(&command) {
+            return Err(Box::new(NFFError::new(command,
+                "only solids can be part of a csg block")));
+        }
+        if (command == "p" || command == "pp") && !builder.csg.is_empty() &&
+                !matches!(builder.blocks.last(), Some(Block::Group(_))) {
+            // Otherwise there'd be no telling where one closed surface
+            // ends and the next begins.
+            return Err(Box::new(NFFError::new(command,
+                "polygons in a csg block must be between begin and end")));
+        }
+
+        if command == "v" && args.is_empty() {
+            view = Some(parse_view(stream, warn)?);
         } else if command == "b" && args.len() == 3 {
-            scene.background = parse_background(args)?;
+            builder.scene.background = parse_background(args)?;
         } else if command == "pp" && args.len() == 1 {
-            let poly = parse_polygon_patch(args, &mut stream)?;
-            scene.add_primitive(Box::new(poly), surface.clone());
+            let vertices = parse_polygon_patch(args, stream)?;
+            builder.add_polygon(vertices);
         } else if command == "p" && args.len() == 1 {
-            let poly = parse_polygon(args, &mut stream)?;
-            scene.add_primitive(Box::new(poly), surface.clone());
+            let vertices = parse_polygon(args, stream)?;
+            builder.add_polygon(vertices);
         } else if command == "f" && args.len() == 8 {
-            surface = parse_fill(use_phong, args)?;
+            builder.set_surface(parse_fill(use_phong, args)?);
         } else if command == "l" && args.len() == 3 {
             let light = parse_white_light(args)?;
-            scene.add_light(light);
+            builder.add_light(light)?;
         } else if command == "l" && args.len() == 6 {
             let light = parse_colored_light(args)?;
-            scene.add_light(light);
+            builder.add_light(light)?;
         } else if command == "s" && args.len() == 4 {
-            let sphere = parse_sphere(args)?;
-            scene.add_primitive(Box::new(sphere), surface.clone());
-        } else if command == "c" && args.len() == 0 {
-            let cone = parse_cone(&mut stream)?;
-            scene.add_primitive(Box::new(cone), surface.clone());
+            builder.add_shape(parse_sphere(args)?);
+        } else if command == "plane" && args.len() == 6 {
+            builder.add_shape(parse_plane(args)?);
+        } else if command == "box" && (args.len() == 6 || args.len() == 10) {
+            builder.add_shape(parse_box(args)?);
+        } else if command == "torus" && args.len() == 8 {
+            builder.add_shape(parse_torus(args)?);
+        } else if command == "c" && args.is_empty() {
+            builder.add_shape(parse_cone(stream)?);
         } else if command == "c" && args.len() == 8 {
-            let cone = parse_cone_one_line(args)?;
-            scene.add_primitive(Box::new(cone), surface.clone());
+            builder.add_shape(parse_cone_one_line(args)?);
+        } else if command == "cc" && args.is_empty() {
+            // Capped cone extension, in the same format as "c"
+            builder.add_shape(parse_cone(stream)?.capped());
+        } else if command == "cc" && args.len() == 8 {
+            builder.add_shape(parse_cone_one_line(args)?.capped());
+        } else if ((command == "ellipsoid" || command == "paraboloid" ||
+                    command == "hyperboloid" || command == "hyperboloid2") &&
+                    (args.len() == 6 || args.len() == 12)) ||
+                (command == "quadric" &&
+                    (args.len() == 10 || args.len() == 16)) {
+            builder.add_shape(parse_quadric(command, args)?);
+        } else if command == "disk" && args.len() == 7 {
+            builder.add_shape(parse_disk(args)?);
+        } else if command == "annulus" && args.len() == 8 {
+            builder.add_shape(parse_annulus(args)?);
+        } else if (command == "translate" && args.len() == 3) ||
+                (command == "rotate" && args.len() == 4) ||
+                (command == "scale" && (args.len() == 1 || args.len() == 3)) {
+            builder.apply_transform(parse_transform(command, args)?);
+        } else if command == "begin" && args.is_empty() {
+            builder.begin();
+        } else if command == "define" && args.len() == 1 {
+            builder.define(args[0])?;
+        } else if command == "end" && args.is_empty() {
+            builder.end()?;
+        } else if command == "instance" && args.len() == 1 {
+            builder.add_instance(args[0])?;
+        } else if command == "csg" && args.len() == 1 {
+            builder.begin_csg(parse_operation(args)?);
         } else {
-            eprintln!("unrecognized command: {}", line);
+            // View extensions may also follow the view block.
+            let handled = match view.as_mut() {
+                Some(view) => parse_view_extension(&line, view)?,
+                None => false
+            };
+            if !handled {
+                warn(&format!("unrecognized command: {}", line.trim_end()));
+            }
         }
     }
 
-    match view {
-        Some(view) => {
-            return Ok((view, scene));
-        },
-        None => {
-            return Err(Box::new(NFFError::new("v", "missing view")));
+    Ok((view, builder.finish()?))
+}
+
+// A shape and the surface it's drawn with
+type Part = (Arc<dyn Shape>, Arc<dyn Surface>);
+
+// Shapes collected between "define" and "end"
+struct Definition {
+    name: String,
+    parts: Vec<Part>
+}
+
+// Solids collected between "csg" and "end"
+struct CsgBlock {
+    operation: Operation,
+    // Surface for the combined solid, from the start of the block
+    surface: Arc<dyn Surface>,
+    operands: Vec<Box<dyn Shape>>,
+    // Polygons of the closed surface being read, if any, other than its
+    // triangles, which are collected in the mesh
+    faces: Vec<Arc<dyn Shape>>
+}
+
+// A "begin", "define" or "csg" line that hasn't been closed by "end" yet,
+// with the transform to go back to when it is
+enum Block {
+    Group(Option<Transform>),
+    Definition(Option<Transform>),
+    Csg(Option<Transform>)
+}
+
+// Builds up the scene from NFF commands, keeping track of the state that
+// applies to new shapes: the current surface and transform, and whether
+// they're part of a definition rather than the scene itself.
+struct SceneBuilder {
+    scene: Scene,
+    surface: Arc<dyn Surface>,
+    // Triangles for the current surface, collected into a mesh so they can
+    // share vertices
+    mesh: MeshBuilder,
+    // Transform for new shapes, or None to leave them as they are
+    transform: Option<Transform>,
+    blocks: Vec<Block>,
+    // Definition being read, if any
+    definition: Option<Definition>,
+    // Finished definitions, as the parts to add for each instance
+    definitions: HashMap<String, Vec<Part>>,
+    // CSG blocks being read, innermost last
+    csg: Vec<CsgBlock>
+}
+
+impl SceneBuilder {
+    fn new() -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene::new(),
+            surface: Arc::new(Phong {
+                color: Color {r: 1.0, g: 1.0, b: 1.0},
+                diffuse_component: 1.0,
+                specular_component: 0.0,
+                shine: 1.0,
+                reflectance: 0.0,
+                transmittance: 0.0,
+                refraction_index: 1.0
+            }),
+            mesh: MeshBuilder::new(),
+            transform: None,
+            blocks: vec![],
+            definition: None,
+            definitions: HashMap::new(),
+            csg: vec![]
+        }
+    }
+
+    // Add a shape with the current surface and transform.
+    fn add_shape(&mut self, shape: impl Shape + 'static) {
+        let shape: Box<dyn Shape> = match &self.transform {
+            Some(transform) => Box::new(
+                Instance::new(Arc::new(shape), transform.clone())),
+            None => Box::new(shape)
+        };
+        let surface = self.surface.clone();
+        self.add_primitive(shape, surface);
+    }
+
+    // Add a shape that's already been transformed. In a csg block, it's the
+    // next solid to combine, and the surface is ignored.
+    fn add_primitive(&mut self,
+            shape: Box<dyn Shape>,
+            surface: Arc<dyn Surface>) {
+        if !self.csg.is_empty() {
+            self.close_surface();
+            if let Some(block) = self.csg.last_mut() {
+                block.operands.push(shape);
+            }
+            return;
+        }
+
+        match &mut self.definition {
+            Some(definition) =>
+                definition.parts.push((Arc::from(shape), surface)),
+            None => self.scene.add_primitive(shape, surface)
+        }
+    }
+
+    // Add a polygon, or add it to the mesh being built if it's a triangle.
+    // Polygons are transformed by moving their vertices, rather than by
+    // making instances of them. In a csg block, the polygons between a
+    // "begin" and "end" make up one closed surface.
+    fn add_polygon(&mut self, mut vertices: Vec<PointNormal>) {
+        if let Some(transform) = &self.transform {
+            for vertex in &mut vertices {
+                vertex.point = transform.apply_point(&vertex.point);
+                vertex.normal =
+                    transform.apply_normal(&vertex.normal).normalized();
+            }
+        }
+
+        if let [a, b, c] = &vertices[..] {
+            self.mesh.add_triangle([&a.point, &b.point, &c.point],
+                [&a.normal, &b.normal, &c.normal]);
+            return;
+        }
+
+        let polygon = Polygon::new(vertices);
+        match self.csg.last_mut() {
+            Some(block) => block.faces.push(Arc::new(polygon)),
+            None => {
+                let surface = self.surface.clone();
+                self.add_primitive(Box::new(polygon), surface);
+            }
+        }
+    }
+
+    // Add the triangles collected in the mesh with the current surface, and
+    // start a new mesh.
+    fn flush_mesh(&mut self) {
+        if self.mesh.is_empty() {
+            return;
+        }
+
+        let mesh = std::mem::take(&mut self.mesh).build();
+        if let Some(block) = self.csg.last_mut() {
+            for triangle in TriangleMesh::triangles(&Arc::new(mesh)) {
+                block.faces.push(Arc::new(triangle));
+            }
+            return;
+        }
+
+        match &mut self.definition {
+            Some(definition) => {
+                definition.parts.push(
+                    (Arc::new(MeshShape::new(mesh)), self.surface.clone()));
+            },
+            None => self.scene.add_mesh(mesh, self.surface.clone())
+        }
+    }
+
+    // Finish the closed surface being read in a csg block, if there is one,
+    // and make it the next solid to combine.
+    fn close_surface(&mut self) {
+        self.flush_mesh();
+        if let Some(block) = self.csg.last_mut() {
+            if !block.faces.is_empty() {
+                let faces = std::mem::take(&mut block.faces);
+                block.operands.push(Box::new(Group::closed(faces)));
+            }
+        }
+    }
+
+    fn set_surface(&mut self, surface: Arc<dyn Surface>) {
+        // Triangles with different surfaces go in different meshes.
+        self.flush_mesh();
+        self.surface = surface;
+    }
+
+    fn add_light(&mut self, mut light: Light) -> Result<(), NFFError> {
+        if self.definition.is_some() {
+            return Err(NFFError::new("l",
+                "lights can't be part of a definition"));
+        }
+        if let Some(transform) = &self.transform {
+            light.position = transform.apply_point(&light.position);
+        }
+        self.scene.add_light(light);
+        Ok(())
+    }
+
+    // Apply `transform` to new shapes, before the current transform. Each
+    // transform is then relative to the ones before it: "translate" then
+    // "rotate" turns shapes about their own origin and then moves them.
+    fn apply_transform(&mut self, transform: Transform) {
+        self.transform = Some(match &self.transform {
+            Some(current) => transform.then(current),
+            None => transform
+        });
+    }
+
+    fn begin(&mut self) {
+        if !self.csg.is_empty() {
+            self.close_surface();
+        }
+        self.blocks.push(Block::Group(self.transform.clone()));
+    }
+
+    // Start collecting shapes for a definition, in their own coordinates.
+    fn define(&mut self, name: &str) -> Result<(), NFFError> {
+        if self.definition.is_some() {
+            return Err(NFFError::new("define",
+                "definitions can't be nested"));
         }
+
+        self.flush_mesh();
+        self.blocks.push(Block::Definition(self.transform.take()));
+        self.definition = Some(Definition {
+            name: name.to_owned(),
+            parts: vec![]
+        });
+        Ok(())
+    }
+
+    // Start collecting solids to combine with `operation`.
+    fn begin_csg(&mut self, operation: Operation) {
+        self.close_surface();
+        self.blocks.push(Block::Csg(self.transform.clone()));
+        self.csg.push(CsgBlock {
+            operation,
+            surface: self.surface.clone(),
+            operands: vec![],
+            faces: vec![]
+        });
+    }
+
+    fn end(&mut self) -> Result<(), NFFError> {
+        match self.blocks.pop() {
+            Some(Block::Group(transform)) => {
+                if !self.csg.is_empty() {
+                    self.close_surface();
+                }
+                self.transform = transform;
+            },
+            Some(Block::Definition(transform)) => {
+                self.flush_mesh();
+                if let Some(definition) = self.definition.take() {
+                    self.definitions.insert(definition.name,
+                        group_by_surface(definition.parts));
+                }
+                self.transform = transform;
+            },
+            Some(Block::Csg(transform)) => {
+                self.close_surface();
+                self.transform = transform;
+                if let Some(block) = self.csg.pop() {
+                    // Combine the solids in order: "csg difference" with a
+                    // b c gives (a - b) - c.
+                    let operation = block.operation;
+                    let solid = block.operands.into_iter()
+                        .reduce(|first, second| Box::new(Csg {
+                            operation,
+                            first,
+                            second
+                        }))
+                        .ok_or_else(|| NFFError::new("end", "empty csg"))?;
+                    self.add_primitive(solid, block.surface);
+                }
+            },
+            None => {
+                return Err(NFFError::new("end",
+                    "no matching begin, define or csg"));
+            }
+        }
+        Ok(())
+    }
+
+    // Add a copy of a definition with the current transform. The copy
+    // shares its shapes with the definition rather than duplicating them.
+    fn add_instance(&mut self, name: &str) -> Result<(), NFFError> {
+        let parts = match self.definitions.get(name) {
+            Some(parts) => parts.clone(),
+            None => {
+                return Err(NFFError::new("instance", "unknown definition"));
+            }
+        };
+
+        let transform = self.transform.clone()
+            .unwrap_or_else(Transform::identity);
+        if !self.csg.is_empty() {
+            // The whole copy is one solid to combine.
+            let mut shapes: Vec<Arc<dyn Shape>> =
+                parts.into_iter().map(|(shape, _)| shape).collect();
+            let shape = match shapes.len() {
+                1 => shapes.remove(0),
+                _ => Arc::new(Group::new(shapes))
+            };
+            let solid = Instance::new(shape, transform);
+            if !csg::is_solid(&solid) {
+                return Err(NFFError::new("instance",
+                    "only solids can be part of a csg block"));
+            }
+            self.add_primitive(Box::new(solid), self.surface.clone());
+            return Ok(());
+        }
+
+        for (shape, surface) in parts {
+            self.add_primitive(
+                Box::new(Instance::new(shape, transform.clone())), surface);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Scene, NFFError> {
+        if !self.blocks.is_empty() {
+            return Err(NFFError::new("end", "missing end"));
+        }
+
+        self.flush_mesh();
+        Ok(self.scene)
+    }
+}
+
+// Collect the shapes of a definition into one group for each surface, so
+// that each instance of it only adds a primitive per surface.
+fn group_by_surface(parts: Vec<Part>) -> Vec<Part> {
+    let mut groups: Vec<Vec<Part>> = vec![];
+    for part in parts {
+        let group = groups.iter_mut()
+            .find(|group| Arc::ptr_eq(&group[0].1, &part.1));
+        match group {
+            Some(group) => group.push(part),
+            None => groups.push(vec![part])
+        }
+    }
+
+    groups.into_iter().map(|mut group| {
+        if group.len() == 1 {
+            return group.remove(0);
+        }
+        let surface = group[0].1.clone();
+        let shapes = group.into_iter().map(|(shape, _)| shape).collect();
+        (Arc::new(Group::new(shapes)) as Arc<dyn Shape>, surface)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEW: &str = "v
+from 0 0 5
+at 0 0 0
+up 0 1 0
+angle 45
+hither 0.1
+resolution 32 32
+";
+
+    fn read_str(nff: &str) -> Result<(Option<View>, Scene), Box<dyn Error>> {
+        read_scene(&mut nff.as_bytes(), true)
+    }
+
+    #[test]
+    fn view_extensions() {
+        let (view, _) =
+            read_str(&format!("{}aperture 0.1
+roll 30
+", VIEW)).unwrap();
+        let view = view.unwrap();
+        assert_eq!(view.aperture, 0.1);
+        assert_eq!(view.roll, 30.0);
+
+        // Comments and blank lines among the view parameters aren't taken
+        // for extensions.
+        let mut warnings = vec![];
+        let (view, _) = read_scene_with_warnings(&mut "v
+# The camera
+from 0 0 5
+
+at 0 0 0
+   \t
+up 0 1 0
+roll 30
+# Field of view
+angle 45
+hither 0.1
+resolution 32 32
+".as_bytes(), true, &mut |warning| warnings.push(warning.to_string()))
+            .unwrap();
+        assert_eq!(warnings, Vec::<String>::new());
+        assert_eq!(view.unwrap().roll, 30.0);
+    }
+
+    #[test]
+    fn wrong_view_extension_value_count() {
+        for extension in ["aperture 0.1 0.2", "focus", "yon 1 2", "roll",
+                "projection fisheye perspective"] {
+            let error = read_str(&format!("{}{}
+", VIEW, extension))
+                .err().expect(extension);
+            let command = extension.split(' ').next().unwrap();
+            assert_eq!(error.to_string(), format!(
+                "Error parsing command {}: wrong value count", command));
+        }
+    }
+
+    // A box between `min` and `max` as a closed surface of polygons, wound
+    // counter-clockwise seen from outside
+    fn box_polygons(min: [f32; 3], max: [f32; 3]) -> String {
+        let faces = [
+            [(0, 0, 0), (0, 0, 1), (0, 1, 1), (0, 1, 0)],
+            [(1, 0, 0), (1, 1, 0), (1, 1, 1), (1, 0, 1)],
+            [(0, 0, 0), (1, 0, 0), (1, 0, 1), (0, 0, 1)],
+            [(0, 1, 0), (0, 1, 1), (1, 1, 1), (1, 1, 0)],
+            [(0, 0, 0), (0, 1, 0), (1, 1, 0), (1, 0, 0)],
+            [(0, 0, 1), (1, 0, 1), (1, 1, 1), (0, 1, 1)]];
+        let value = |axis: usize, end: usize|
+            if end == 0 {min[axis]} else {max[axis]};
+
+        let mut nff = String::from("begin\n");
+        for face in &faces {
+            nff += "p 4\n";
+            for &(x, y, z) in face {
+                nff += &format!("{} {} {}\n",
+                    value(0, x), value(1, y), value(2, z));
+            }
+        }
+        nff + "end\n"
+    }
+
+    #[test]
+    fn csg_closed_surfaces() {
+        let (_, scene) = read_str(&format!("csg difference\n{}{}end\n",
+            box_polygons([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]),
+            box_polygons([-0.5, -0.5, -2.0], [0.5, 0.5, 2.0]))).unwrap();
+        let space_partition = scene.build_space_partition();
+        let coverage = |x: f32, y: f32| scene.trace_coverage(&space_partition,
+            &Point {x, y, z: 5.0}, &Vector {dx: 0.0, dy: 0.0, dz: -1.0},
+            0.0, f32::INFINITY).1;
+
+        // The second box is a hole through the first.
+        assert_eq!(coverage(0.0, 0.0), 0.0);
+        assert_eq!(coverage(0.75, 0.0), 1.0);
+    }
+
+    #[test]
+    fn csg_polygons_outside_begin() {
+        let error = read_str("csg union\np 3\n0 0 0\n1 0 0\n0 1 0\nend\n")
+            .err().unwrap();
+        assert_eq!(error.to_string(), "Error parsing command p: polygons in \
+            a csg block must be between begin and end");
+    }
+
+    #[test]
+    fn csg_solids() {
+        read_str("define ball
+s 0 0 0 1
+end
+csg difference
+torus 0 0 0 0 1 0 2 0.5
+ellipsoid 2 0 0 0.5 1 0.5
+quadric 1 1 1 0 0 0 0 0 0 -1 -2 -2 -2 2 0 2
+translate -2 0 0
+instance ball
+end
+").unwrap();
+
+        let error = read_str("define flat
+disk 0 0 0 0 1 0 1
+end
+csg union
+s 0 0 0 1
+instance flat
+end
+").err().unwrap();
+        assert_eq!(error.to_string(), "Error parsing command instance: only \
+            solids can be part of a csg block");
     }
 }
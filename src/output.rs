@@ -0,0 +1,107 @@
+//! Writing rendered images to files in the supported formats.
+
+use std::fmt;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+
+use crate::render::RenderTarget;
+use crate::ppm;
+use crate::png;
+use crate::pfm;
+use crate::hdr;
+use crate::png::BitDepth;
+use crate::tone_map::ToneMapping;
+
+
+/// Error for an output path whose extension isn't a supported format.
+#[derive(Debug, Clone)]
+pub struct UnknownFormatError {
+    path: String
+}
+
+impl fmt::Display for UnknownFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unable to determine an output format for {} \
+            (supported extensions: .ppm, .png, .pfm, .hdr)", self.path)
+    }
+}
+
+impl Error for UnknownFormatError {
+}
+
+/// Image file formats that renders can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Binary PPM, 8 bits per channel
+    Ppm,
+    /// PNG, 8 or 16 bits per channel, optionally with alpha
+    Png,
+    /// Portable float map, with linear 32-bit floating point channels
+    Pfm,
+    /// Radiance RGBE, with linear high dynamic range color
+    Hdr
+}
+
+/// Encoder settings; formats ignore the settings they don't support.
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Bits per channel (PNG only)
+    pub bit_depth: BitDepth,
+    /// Whether to write pixel coverage as an alpha channel (PNG only)
+    pub alpha: bool,
+    /// Conversion from linear color for low dynamic range formats (PPM and
+    /// PNG); HDR formats always get the linear values.
+    pub tone_mapping: ToneMapping
+}
+
+impl Default for OutputOptions {
+    fn default() -> OutputOptions {
+        OutputOptions {
+            bit_depth: BitDepth::Eight,
+            alpha: false,
+            tone_mapping: ToneMapping::default()
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Choose the output format from the extension of `path`, ignoring case.
+    /// "-" stands for stdout, which gets the default format (PPM).
+    pub fn from_path(path: &str) -> Result<OutputFormat, UnknownFormatError> {
+        if path == "-" {
+            return Ok(OutputFormat::Ppm);
+        }
+
+        let extension = Path::new(path).extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("ppm") => Ok(OutputFormat::Ppm),
+            Some("png") => Ok(OutputFormat::Png),
+            Some("pfm") => Ok(OutputFormat::Pfm),
+            Some("hdr") => Ok(OutputFormat::Hdr),
+            _ => Err(UnknownFormatError {path: path.to_owned()})
+        }
+    }
+}
+
+/// Encode `image` in the given format to `stream`.
+pub fn write(image: &RenderTarget,
+        format: OutputFormat,
+        options: &OutputOptions,
+        stream: &mut dyn Write) -> std::io::Result<()> {
+    match format {
+        OutputFormat::Ppm => {
+            let image = options.tone_mapping.apply_to_image(image);
+            ppm::write(&image, stream)
+        },
+        OutputFormat::Png => {
+            let image = options.tone_mapping.apply_to_image(image);
+            png::write(&image, options.bit_depth, options.alpha, stream)
+        },
+        OutputFormat::Pfm => pfm::write(image, stream),
+        OutputFormat::Hdr => hdr::write(image, stream)
+    }
+}
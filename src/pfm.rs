@@ -0,0 +1,59 @@
+use std::io::Write;
+
+use crate::render::RenderTarget;
+
+
+// Write `image` as a color Portable Float Map, keeping the full unclamped
+// range of the colors as 32-bit floats.
+pub fn write(image: &RenderTarget, stream: &mut dyn Write) ->
+        std::io::Result<()> {
+    // A negative scale marks the data as little-endian.
+    writeln!(stream, "PF")?;
+    writeln!(stream, "{} {}", image.width, image.height)?;
+    writeln!(stream, "-1.0")?;
+
+    // PFM scanlines are stored from the bottom of the image to the top.
+    let mut row = Vec::with_capacity(image.width * 12);
+    for j in (0..image.height).rev() {
+        row.clear();
+        for i in 0..image.width {
+            let color = image.get(i, j);
+            row.extend_from_slice(&color.r.to_le_bytes());
+            row.extend_from_slice(&color.g.to_le_bytes());
+            row.extend_from_slice(&color.b.to_le_bytes());
+        }
+        stream.write_all(&row[..])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn layout() {
+        let mut image = RenderTarget::new(2, 2);
+        image.set(0, 0, Color {r: 1.0, g: 0.5, b: 0.25});
+        image.set(1, 0, Color {r: 2.5, g: 0.0, b: -1.0});
+        image.set(0, 1, Color {r: 0.0, g: 0.0, b: 0.0});
+        image.set(1, 1, Color {r: 100.0, g: 1e-3, b: 3.0});
+
+        let mut encoded = vec![];
+        write(&image, &mut encoded).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(encoded[..header.len()], header[..]);
+
+        // Little-endian floats, from the bottom row up
+        let data = &encoded[header.len()..];
+        assert_eq!(data[..4], [0, 0, 0, 0]);
+        assert_eq!(data[12..16], [0x00, 0x00, 0xc8, 0x42]);
+        let values: Vec<f32> = data.chunks(4)
+            .map(|bytes| f32::from_le_bytes(
+                [bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        assert_eq!(values, [0.0, 0.0, 0.0, 100.0, 1e-3, 3.0,
+            1.0, 0.5, 0.25, 2.5, 0.0, -1.0]);
+    }
+}
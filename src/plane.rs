@@ -0,0 +1,75 @@
+use crate::vector_math;
+use crate::vector_math::{Point, Vector};
+use crate::shape::{Shape, IntersectResult, BoundingBox, Interval};
+
+
+/// An infinite plane through `point`, facing the direction of `normal`.
+pub struct Plane {
+    /// Any point on the plane
+    pub point: Point,
+    /// Direction the plane faces
+    pub normal: Vector
+}
+
+impl Shape for Plane {
+    // Planes go on forever, so they can't be bounded.
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
+
+    // As a solid, a plane is the half of space behind it, away from the
+    // direction it faces.
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        let normal = self.normal.normalized();
+        let infinitely = |dist: f32| IntersectResult {
+            normal: Vector {dx: 0.0, dy: 0.0, dz: 0.0},
+            dist
+        };
+
+        let den = vector_math::dot(ray, &normal);
+        let height = vector_math::dot(&(src - &self.point), &normal);
+        if den.abs() < 0.000001 {
+            // Running along the plane, the line is either all behind it or
+            // all in front of it.
+            return Some(if height <= 0.0 {
+                vec![Interval {
+                    enter: infinitely(f32::NEG_INFINITY),
+                    exit: infinitely(f32::INFINITY)
+                }]
+            } else {
+                vec![]
+            });
+        }
+
+        let crossing = IntersectResult {
+            normal: normal.clone(),
+            dist: -height / den
+        };
+        Some(vec![if den < 0.0 {
+            Interval {enter: crossing, exit: infinitely(f32::INFINITY)}
+        } else {
+            Interval {enter: infinitely(f32::NEG_INFINITY), exit: crossing}
+        }])
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        let normal = self.normal.normalized();
+
+        let den = vector_math::dot(ray, &normal);
+        if den.abs() < 0.000001 {
+            // The ray is (very nearly) parallel to the plane.
+            return None;
+        }
+
+        let dist = vector_math::dot(&(&self.point - src), &normal) / den;
+        if dist < near {
+            return None;
+        }
+
+        Some(IntersectResult {
+            normal,
+            dist
+        })
+    }
+}
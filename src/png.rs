@@ -0,0 +1,170 @@
+use std::io::Write;
+
+use crate::render::RenderTarget;
+
+
+/// Number of bits used for each channel of a PNG image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitDepth {
+    /// 8 bits per channel
+    Eight,
+    /// 16 bits per channel
+    Sixteen
+}
+
+// Write `image` as a PNG with 8 or 16 bits per channel. If `alpha` is set,
+// the pixel coverage is written as a fourth channel, so the background will
+// be transparent.
+pub fn write(image: &RenderTarget,
+        bit_depth: BitDepth,
+        alpha: bool,
+        stream: &mut dyn Write) -> std::io::Result<()> {
+    let mut encoder = ::png::Encoder::new(
+        stream, image.width as u32, image.height as u32);
+    encoder.set_color(if alpha {
+        ::png::ColorType::Rgba
+    } else {
+        ::png::ColorType::Rgb
+    });
+    encoder.set_depth(match bit_depth {
+        BitDepth::Eight => ::png::BitDepth::Eight,
+        BitDepth::Sixteen => ::png::BitDepth::Sixteen
+    });
+
+    let channels = if alpha { 4 } else { 3 };
+    let bytes_per_channel = match bit_depth {
+        BitDepth::Eight => 1,
+        BitDepth::Sixteen => 2
+    };
+
+    let mut data = Vec::with_capacity(
+        image.width * image.height * channels * bytes_per_channel);
+    for j in 0..image.height {
+        for i in 0..image.width {
+            let mut color = image.get(i, j).clone();
+            color.clamp();
+            let mut values = vec![color.r, color.g, color.b];
+            if alpha {
+                values.push(image.get_alpha(i, j));
+            }
+
+            for value in values {
+                match bit_depth {
+                    BitDepth::Eight => {
+                        data.push((value * 255.9) as u8);
+                    },
+                    BitDepth::Sixteen => {
+                        // PNG samples are big-endian
+                        let sample = (value * 65535.9) as u16;
+                        data.extend_from_slice(&sample.to_be_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    // Channel values used in the test image, as fractions of full scale
+    const ZERO: usize = 0;
+    const QUARTER: usize = 1;
+    const HALF: usize = 2;
+    const FULL: usize = 3;
+
+    // A 3x2 image with every level in every channel, a color that has to be
+    // clipped, and a range of coverage. Each pixel is listed with the levels
+    // its channels should be written at.
+    fn test_image() -> (RenderTarget, Vec<[usize; 4]>) {
+        let pixels = [
+            (0.0, 0.0, 0.0, 0.0, [ZERO, ZERO, ZERO, ZERO]),
+            (1.0, 1.0, 1.0, 1.0, [FULL, FULL, FULL, FULL]),
+            (0.0, 0.5, 0.0, 1.0, [ZERO, HALF, ZERO, FULL]),
+            (2.0, 0.5, 0.25, 0.5, [FULL, HALF, QUARTER, HALF]),
+            (0.25, 0.0, 1.0, 0.25, [QUARTER, ZERO, FULL, QUARTER]),
+            (0.5, 0.5, 0.5, 0.0, [HALF, HALF, HALF, ZERO])];
+
+        let mut image = RenderTarget::new(3, 2);
+        for (index, &(r, g, b, alpha, _)) in pixels.iter().enumerate() {
+            image.set_with_alpha(index % 3, index / 3, Color {r, g, b}, alpha);
+        }
+        (image, pixels.iter().map(|pixel| pixel.4).collect())
+    }
+
+    // Write the test image and decode it again, returning the header and
+    // the samples.
+    fn round_trip(bit_depth: BitDepth, alpha: bool) ->
+            (::png::OutputInfo, Vec<u32>) {
+        let (image, _) = test_image();
+        let mut encoded = Vec::new();
+        write(&image, bit_depth, alpha, &mut encoded).unwrap();
+
+        let mut decoder = ::png::Decoder::new(&encoded[..]);
+        decoder.set_transformations(::png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        data.truncate(info.buffer_size());
+
+        let samples = match bit_depth {
+            BitDepth::Eight => data.iter().map(|&value| value as u32).collect(),
+            BitDepth::Sixteen => data.chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as u32)
+                .collect()
+        };
+        (info, samples)
+    }
+
+    fn check(bit_depth: BitDepth, alpha: bool) {
+        let (info, samples) = round_trip(bit_depth, alpha);
+
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.color_type, if alpha {
+            ::png::ColorType::Rgba
+        } else {
+            ::png::ColorType::Rgb
+        });
+        let (png_depth, values) = match bit_depth {
+            BitDepth::Eight => (::png::BitDepth::Eight, [0, 63, 127, 255]),
+            BitDepth::Sixteen =>
+                (::png::BitDepth::Sixteen, [0, 16383, 32767, 65535])
+        };
+        assert_eq!(info.bit_depth, png_depth);
+
+        let channels = if alpha { 4 } else { 3 };
+        let (_, levels) = test_image();
+        let expected: Vec<u32> = levels.iter()
+            .flat_map(|pixel| &pixel[..channels])
+            .map(|&level| values[level])
+            .collect();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn eight_bit_rgb() {
+        check(BitDepth::Eight, false);
+    }
+
+    #[test]
+    fn eight_bit_rgba() {
+        check(BitDepth::Eight, true);
+    }
+
+    #[test]
+    fn sixteen_bit_rgb() {
+        check(BitDepth::Sixteen, false);
+    }
+
+    #[test]
+    fn sixteen_bit_rgba() {
+        check(BitDepth::Sixteen, true);
+    }
+}
@@ -3,12 +3,213 @@ use crate::vector_math::{Point, Vector, PointNormal};
 use crate::shape::{Shape, IntersectResult, BoundingBox};
 
 
+/// A flat polygon, which may be concave. Normals given at the vertices are
+/// interpolated across it for smooth shading.
 pub struct Polygon {
-    pub vertices: Vec<PointNormal>
+    vertices: Vec<PointNormal>,
+    // None if the polygon has no area, so rays never hit it
+    plane: Option<PolygonPlane>
+}
+
+// A point on the plane of a polygon, in the polygon's 2D basis.
+#[derive(Debug, Clone, Copy)]
+struct PlanePoint {
+    u: f32,
+    v: f32
+}
+
+// The plane of a polygon, with a 2D basis in it that has the first vertex
+// at the origin and the u axis pointing at the next vertex that isn't on
+// top of it.
+struct PolygonPlane {
+    // Unit normal, by Newell's method
+    normal: Vector,
+    u_axis: Vector,
+    v_axis: Vector,
+    // The vertices in the 2D basis
+    corners: Vec<PlanePoint>
+}
+
+// Twice the signed area of the triangle (a, b, c); positive if the points
+// are counter-clockwise.
+fn orientation(a: PlanePoint, b: PlanePoint, c: PlanePoint) -> f32 {
+    (b.u - a.u) * (c.v - a.v) - (b.v - a.v) * (c.u - a.u)
+}
+
+impl PolygonPlane {
+    fn new(vertices: &[PointNormal]) -> Option<PolygonPlane> {
+        let normal = newell_normal(vertices);
+        let area = normal.magnitude();
+        if area == 0.0 {
+            return None;
+        }
+        let normal = normal / area;
+
+        let origin = &vertices[0].point;
+        let u_axis = vertices[1..].iter()
+            .map(|vertex| &vertex.point - origin)
+            .find(|edge| edge.magnitude() > 0.0)?
+            .normalized();
+        let v_axis = vector_math::cross(&normal, &u_axis);
+
+        let mut plane = PolygonPlane {
+            normal,
+            u_axis,
+            v_axis,
+            corners: Vec::with_capacity(vertices.len())
+        };
+        plane.corners = vertices.iter()
+            .map(|vertex| plane.project(&(&vertex.point - origin)))
+            .collect();
+        Some(plane)
+    }
+
+    // Coordinates of an offset from the first vertex, in the plane's basis
+    fn project(&self, offset: &Vector) -> PlanePoint {
+        PlanePoint {
+            u: vector_math::dot(offset, &self.u_axis),
+            v: vector_math::dot(offset, &self.v_axis)
+        }
+    }
+}
+
+// Normal of the plane of `vertices` by Newell's method, which averages over
+// every edge, so it points the right way even for concave polygons. The
+// length is twice the polygon's area.
+fn newell_normal(vertices: &[PointNormal]) -> Vector {
+    let mut normal = Vector {dx: 0.0, dy: 0.0, dz: 0.0};
+    for (i, vertex) in vertices.iter().enumerate() {
+        let point = &vertex.point;
+        let next = &vertices[(i + 1) % vertices.len()].point;
+        normal.dx += (point.y - next.y) * (point.z + next.z);
+        normal.dy += (point.z - next.z) * (point.x + next.x);
+        normal.dz += (point.x - next.x) * (point.y + next.y);
+    }
+    normal
+}
+
+impl Polygon {
+    /// Polygon with the given corners in order around it, counter-clockwise
+    /// when viewed from the side it faces. There must be at least three.
+    pub fn new(vertices: Vec<PointNormal>) -> Polygon {
+        let plane = PolygonPlane::new(&vertices);
+        Polygon {vertices, plane}
+    }
+
+    /// Corners of the polygon, with the normals at each of them
+    pub fn vertices(&self) -> &[PointNormal] {
+        &self.vertices
+    }
+
+    // Check whether `point` is inside the polygon, counting the edges
+    // crossed by a ray from it along +u; an odd number means it's inside.
+    // Each edge includes its lower endpoint but not its upper one, so a ray
+    // through a vertex counts exactly one of the two edges meeting there,
+    // and the crossing itself is found from the sign of an orientation
+    // test rather than by dividing to find where it is.
+    fn contains(corners: &[PlanePoint], point: PlanePoint) -> bool {
+        let mut inside = false;
+        for (i, &a) in corners.iter().enumerate() {
+            let b = corners[(i + 1) % corners.len()];
+            if (a.v <= point.v) != (b.v <= point.v) {
+                // The edge spans the ray's line; it crosses the ray if the
+                // point is on the left of an upward edge or the right of a
+                // downward one. A point right on the edge is on neither
+                // side, so an edge shared by two polygons has the point in
+                // exactly one of them.
+                let side = orientation(a, b, point);
+                let crosses = if b.v > a.v { side > 0.0 } else { side < 0.0 };
+                if crosses {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    // Interpolate the vertex normals at `point` using mean value
+    // coordinates (Floater 2003, extended to arbitrary polygons by Hormann
+    // and Floater 2006). These vary smoothly inside the polygon, reduce to
+    // linear interpolation along each edge and barycentric coordinates for
+    // triangles, and take every vertex into account, so smooth patches
+    // shade without facets.
+    fn interpolate_normal(&self, corners: &[PlanePoint], point: PlanePoint) ->
+            Vector {
+        let count = corners.len();
+        let to_corner = |i: usize| {
+            let (du, dv) = (corners[i].u - point.u, corners[i].v - point.v);
+            (du, dv, (du * du + dv * dv).sqrt())
+        };
+
+        // Very close to a vertex, the direction to it is swamped by
+        // rounding error, so treat the point as being right on it.
+        let mut size = 0.0f32;
+        let mut nearest = (0, f32::INFINITY);
+        for i in 0..count {
+            let (_, _, distance) = to_corner(i);
+            size = size.max(distance);
+            if distance < nearest.1 {
+                nearest = (i, distance);
+            }
+        }
+        if nearest.1 <= 1e-4 * size {
+            return self.vertices[nearest.0].normal.normalized();
+        }
+
+        // tan(a / 2) for the angle a subtended by the edge from corner `i`
+        // to the next, or None if the point is right on that edge
+        let half_tangent = |i: usize| {
+            let (du, dv, distance) = to_corner(i);
+            let (next_du, next_dv, next_distance) = to_corner((i + 1) % count);
+            let cross = du * next_dv - dv * next_du;
+            let dot = du * next_du + dv * next_dv;
+            let length_product = distance * next_distance;
+            if dot < 0.0 && cross.abs() <= 1e-6 * length_product {
+                None
+            } else if dot < 0.0 {
+                // sin(a) / (1 + cos(a)) and (1 - cos(a)) / sin(a) are
+                // equal, but the first loses precision as the angle
+                // approaches 180 degrees (near the edge) and the second as
+                // it approaches 0.
+                Some((length_product - dot) / cross)
+            } else {
+                Some(cross / (length_product + dot))
+            }
+        };
+
+        // Right on an edge, the coordinates reduce to linear interpolation
+        // between its ends.
+        let edge_normal = |i: usize, next: usize| {
+            let (_, _, distance) = to_corner(i);
+            let (_, _, next_distance) = to_corner(next);
+            let scale = next_distance / (distance + next_distance);
+            vector_math::interpolate(&self.vertices[i].normal,
+                &self.vertices[next].normal, scale)
+        };
+
+        let mut normal = Vector {dx: 0.0, dy: 0.0, dz: 0.0};
+        let mut previous_tangent = match half_tangent(count - 1) {
+            Some(tangent) => tangent,
+            None => return edge_normal(count - 1, 0)
+        };
+        for i in 0..count {
+            let next = (i + 1) % count;
+            let tangent = match half_tangent(i) {
+                Some(tangent) => tangent,
+                None => return edge_normal(i, next)
+            };
+
+            let (_, _, distance) = to_corner(i);
+            let weight = (previous_tangent + tangent) / distance;
+            normal = normal + &self.vertices[i].normal * weight;
+            previous_tangent = tangent;
+        }
+        normal.normalized()
+    }
 }
 
 impl Shape for Polygon {
-    fn  bounding_box(&self) -> BoundingBox {
+    fn bounding_box(&self) -> Option<BoundingBox> {
         let mut min_x = self.vertices[0].point.x;
         let mut max_x = min_x;
         let mut min_y = self.vertices[0].point.y;
@@ -41,7 +242,7 @@ impl Shape for Polygon {
         // let min_x = self.vertices.iter().map(|pn| pn.point.x).min();
         // ...
 
-        BoundingBox {
+        Some(BoundingBox {
             corner: Point {
                 x: min_x,
                 y: min_y,
@@ -52,17 +253,14 @@ impl Shape for Polygon {
                 dy: max_y - min_y,
                 dz: max_z - min_z
             }
-        }
+        })
     }
 
     fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
             Option<IntersectResult> {
+        let plane = self.plane.as_ref()?;
 
-        let edge1 = &self.vertices[1].point - &self.vertices[0].point;
-        let edge2 = &self.vertices[2].point - &self.vertices[0].point;
-        let geometric_normal = vector_math::cross(&edge1, &edge2);
-
-        let den = vector_math::dot(&ray, &geometric_normal);
+        let den = vector_math::dot(ray, &plane.normal);
         if den.abs() < 0.000001 {
             // This means the ray is (very nearly) parallel to the plane of
             // the polygon - no intersection possible.
@@ -70,8 +268,7 @@ impl Shape for Polygon {
         }
 
         let to_v1 = &self.vertices[0].point - src;
-        let num = vector_math::dot(&to_v1, &geometric_normal);
-        let src_to_plane_dist = num / den;
+        let src_to_plane_dist = vector_math::dot(&to_v1, &plane.normal) / den;
 
         if src_to_plane_dist < near {
             // The distance to the polygon's plane is less than the near
@@ -79,216 +276,280 @@ impl Shape for Polygon {
             return None;
         }
 
-        // The general approach here is to draw another ray away from the point
-        // where the ray intersects the polygon's plane, then count the number
-        // of edges intersected - an odd number means intersection point was
-        // inside the polygon.  Unlike some other methods, this works with
-        // non-convex polygons.
-
-        // To simplify the math we'll project the polygon onto an axis-aligned
-        // plane, determined by the smaller two components of the geometric
-        // normal.
-
-        // TO DO:  A better approach for this would be to change the basis
-        // vectors for the polygon vertices, such that all the points are in
-        // a 2D plane with index 0 at (0, 0) and index 1 at (x, 0).  Then you
-        // wouldn't need the 3 projection cases and the whole thing would
-        // probably be more numerically stable.  There is probably also a
-        // better way to do the normal interpolation - the current method
-        // ignores most of the edge data.
-
-        let in_plane = src + ray * src_to_plane_dist;
-        let mut edge_intersection_count: u32 = 0;
-        let mut nearest_forward_dist: Option<f32> = None;
-        let mut nearest_forward_scale = 0.0;
-        let mut nearest_forward_index = 0;
-        let mut nearest_reverse_dist: Option<f32> = None;
-        let mut nearest_reverse_scale = 0.0;
-        let mut nearest_reverse_index = 0;
-
-        if geometric_normal.dz.abs() > geometric_normal.dx.abs()
-        && geometric_normal.dz.abs() > geometric_normal.dy.abs() {
-            // Largest normal component is z, so polygon's major plane is x-y.
-            // We'll trace along x.
-
-            for i in 0..self.vertices.len() {
-                let point = &self.vertices[i].point;
-                let next_point =
-                    &self.vertices[(i + 1) % self.vertices.len()].point;
-                let edge = point - next_point;
-
-                if edge.dy.abs() < 0.000001 {
-                    // The edge is (very nearly) parallel to our trace - no
-                    // intersection possible.
-                    continue;
-                }
+        let in_plane = plane.project(&(ray * src_to_plane_dist - &to_v1));
+        if !Polygon::contains(&plane.corners, in_plane) {
+            return None;
+        }
 
-                let scale = (in_plane.y - next_point.y) / edge.dy;
-                if scale < 0.0 || scale > 1.0 {
-                    // Scale is the relative position between the two edge
-                    // endpoints - here we're outside both, so no intersection.
-                    continue;
-                }
+        Some(IntersectResult {
+            normal: self.interpolate_normal(&plane.corners, in_plane),
+            dist: src_to_plane_dist
+        })
+    }
 
-                let to_edge_dist = scale * edge.dx + next_point.x - in_plane.x;
-                if to_edge_dist >= 0.0 {
-                    edge_intersection_count += 1;
-
-                    if let Some(dist) = nearest_forward_dist {
-                        if to_edge_dist < dist {
-                            nearest_forward_dist = None
-                        }
-                    }
-                    if let None = nearest_forward_dist {
-                        nearest_forward_dist = Some(to_edge_dist);
-                        nearest_forward_index = i;
-                        nearest_forward_scale = scale;
-                    }
-                } else {
-                    // The edge intersection is backwards along the trace.
-                    // This won't count for an intersection, but we need to
-                    // track which edge is closest to calculate a final
-                    // result.
-
-                    if let Some(dist) = nearest_reverse_dist {
-                        if to_edge_dist > dist {
-                            nearest_reverse_dist = None
-                        }
-                    }
-                    if let None = nearest_reverse_dist {
-                        nearest_reverse_dist = Some(to_edge_dist);
-                        nearest_reverse_index = i;
-                        nearest_reverse_scale = scale;
-                    }
-                }
-            }
-        } else if geometric_normal.dy.abs() > geometric_normal.dx.abs() {
-            // Largest normal component is y, so polygon's major plane is x-z.
-            // Trace along x again...
-
-            for i in 0..self.vertices.len() {
-                let point = &self.vertices[i].point;
-                let next_point =
-                    &self.vertices[(i + 1) % self.vertices.len()].point;
-                let edge = point - next_point;
-
-                if edge.dz.abs() < 0.000001 {
-                    continue;
-                }
+    fn face_normal(&self, hit: &IntersectResult) -> Vector {
+        let normal = match &self.plane {
+            Some(plane) => plane.normal.clone(),
+            None => return hit.normal.clone()
+        };
+        if vector_math::dot(&normal, &hit.normal) < 0.0 {
+            -&normal
+        } else {
+            normal
+        }
+    }
+}
 
-                let scale = (in_plane.z - next_point.z) / edge.dz;
-                if scale < 0.0 || scale > 1.0 {
-                    continue;
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                let to_edge_dist = scale * edge.dx + next_point.x - in_plane.x;
-                if to_edge_dist >= 0.0 {
-                    edge_intersection_count += 1;
-
-                    if let Some(dist) = nearest_forward_dist {
-                        if to_edge_dist < dist {
-                            nearest_forward_dist = None
-                        }
-                    }
-                    if let None = nearest_forward_dist {
-                        nearest_forward_dist = Some(to_edge_dist);
-                        nearest_forward_index = i;
-                        nearest_forward_scale = scale;
-                    }
-                } else {
-                    if let Some(dist) = nearest_reverse_dist {
-                        if to_edge_dist > dist {
-                            nearest_reverse_dist = None
-                        }
-                    }
-                    if let None = nearest_reverse_dist {
-                        nearest_reverse_dist = Some(to_edge_dist);
-                        nearest_reverse_index = i;
-                        nearest_reverse_scale = scale;
-                    }
-                }
-            }
-        } else {
-            // Largest normal component is x, so polygon's major plane is y-z.
-            // Trace along y.
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {x, y, z}
+    }
 
-            for i in 0..self.vertices.len() {
-                let point = &self.vertices[i].point;
-                let next_point =
-                    &self.vertices[(i + 1) % self.vertices.len()].point;
-                let edge = point - next_point;
+    fn vector(dx: f32, dy: f32, dz: f32) -> Vector {
+        Vector {dx, dy, dz}
+    }
 
-                if edge.dz.abs() < 0.000001 {
-                    continue;
-                }
+    // Polygon in the plane z = 0 facing +z, with the normal at each corner
+    // given by `normal`
+    fn flat_polygon(corners: &[(f32, f32)],
+            normal: impl Fn(f32, f32) -> Vector) -> Polygon {
+        Polygon::new(corners.iter()
+            .map(|&(x, y)| PointNormal {
+                point: point(x, y, 0.0),
+                normal: normal(x, y)
+            })
+            .collect())
+    }
 
-                let scale = (in_plane.z - next_point.z) / edge.dz;
-                if scale < 0.0 || scale > 1.0 {
-                    continue;
-                }
+    fn flat(corners: &[(f32, f32)]) -> Polygon {
+        flat_polygon(corners, |_, _| vector(0.0, 0.0, 1.0))
+    }
 
-                let to_edge_dist = scale * edge.dy + next_point.y - in_plane.y;
-                if to_edge_dist >= 0.0 {
-                    edge_intersection_count += 1;
-
-                    if let Some(dist) = nearest_forward_dist {
-                        if to_edge_dist < dist {
-                            nearest_forward_dist = None
-                        }
-                    }
-                    if let None = nearest_forward_dist {
-                        nearest_forward_dist = Some(to_edge_dist);
-                        nearest_forward_index = i;
-                        nearest_forward_scale = scale;
-                    }
-                } else {
-                    if let Some(dist) = nearest_reverse_dist {
-                        if to_edge_dist > dist {
-                            nearest_reverse_dist = None
-                        }
-                    }
-                    if let None = nearest_reverse_dist {
-                        nearest_reverse_dist = Some(to_edge_dist);
-                        nearest_reverse_index = i;
-                        nearest_reverse_scale = scale;
-                    }
-                }
+    // Where a ray straight down onto (x, y) hits `polygon`
+    fn hit(polygon: &Polygon, x: f32, y: f32) -> Option<IntersectResult> {
+        polygon.intersect(&point(x, y, 1.0), &vector(0.0, 0.0, -1.0), 0.0)
+    }
+
+    fn assert_close(actual: &Vector, expected: &Vector) {
+        assert!((actual - expected).magnitude() < 1e-5,
+            "{:?} != {:?}", actual, expected);
+    }
+
+    // Distance along the ray to where it hits the polygon with corners
+    // `points`, by the algorithm this one replaced: it flattens the polygon
+    // onto the plane of the two axes it's least steep to, and counts the
+    // edges crossed by a line from the hit point.
+    fn old_intersect(points: &[Point], src: &Point, ray: &Vector) ->
+            Option<f32> {
+        let normal =
+            vector_math::cross(&(&points[1] - &points[0]),
+                &(&points[2] - &points[0]));
+        let den = vector_math::dot(ray, &normal);
+        if den.abs() < 0.000001 {
+            return None;
+        }
+        let dist = vector_math::dot(&(&points[0] - src), &normal) / den;
+        if dist < 0.0 {
+            return None;
+        }
+
+        let (x, y, z) = (normal.dx.abs(), normal.dy.abs(), normal.dz.abs());
+        let flatten = |point: &Point| if z >= x && z >= y {
+            (point.x, point.y)
+        } else if y >= x {
+            (point.x, point.z)
+        } else {
+            (point.y, point.z)
+        };
+
+        let (trace, across) = flatten(&(src + &(ray * dist)));
+        let mut crossings = 0;
+        for (i, point) in points.iter().enumerate() {
+            let (point_trace, point_across) = flatten(point);
+            let (next_trace, next_across) =
+                flatten(&points[(i + 1) % points.len()]);
+            let (edge_trace, edge_across) =
+                (point_trace - next_trace, point_across - next_across);
+            if edge_across.abs() < 0.000001 {
+                continue;
+            }
+            let scale = (across - next_across) / edge_across;
+            if !(0.0..=1.0).contains(&scale) {
+                continue;
+            }
+            if scale * edge_trace + next_trace - trace >= 0.0 {
+                crossings += 1;
             }
         }
+        if crossings % 2 == 1 { Some(dist) } else { None }
+    }
+
+    // Distance from `point` to the line segment from `a` to `b`
+    fn segment_distance(point: &Point, a: &Point, b: &Point) -> f32 {
+        let edge = b - a;
+        let along = (vector_math::dot(&(point - a), &edge) /
+            vector_math::dot(&edge, &edge)).clamp(0.0, 1.0);
+        (point - &(a + &(&edge * along))).magnitude()
+    }
 
-        if edge_intersection_count & 1 == 0 {
-            // The trace hit an even number of polygon edges, meaning the
-            // starting point must have been outside - so no intersection.
-            return None
+    #[test]
+    fn agrees_with_old_algorithm_on_convex_polygons() {
+        let mut seed = 1u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // Corners around an ellipse, in a random plane
+            let normal = vector(random() - 0.5, random() - 0.5, random() - 0.5)
+                .normalized();
+            let u_axis = vector_math::cross(&normal, &vector(0.6, 0.8, 0.0))
+                .normalized();
+            let v_axis = vector_math::cross(&normal, &u_axis);
+            let center =
+                point(random() * 4.0 - 2.0, random() * 4.0 - 2.0, random());
+            let (width, height) = (0.5 + random(), 0.5 + random());
+            // The old algorithm finds the plane from the first three
+            // corners, so keep them well apart.
+            let count = 3 + (random() * 6.0) as usize;
+            let points: Vec<Point> = (0..count)
+                .map(|i| (i as f32 + random() * 0.8) / count as f32 *
+                    std::f32::consts::TAU)
+                .map(|angle| &center + &(&(&u_axis * (width * angle.cos())) +
+                    &(&v_axis * (height * angle.sin()))))
+                .collect();
+            let polygon = Polygon::new(points.iter()
+                .map(|point| PointNormal {
+                    point: point.clone(),
+                    normal: normal.clone()
+                })
+                .collect());
+
+            let src = point(random() * 20.0 - 10.0, random() * 20.0 - 10.0,
+                10.0 + random() * 10.0);
+            let target = &center + &(&(&u_axis * (random() * 3.0 - 1.5)) +
+                &(&v_axis * (random() * 3.0 - 1.5)));
+            let ray = (&target - &src).normalized();
+
+            // Rounding can put points right by an edge either side of it.
+            let near_edge = (0..points.len()).any(|i| segment_distance(
+                &target, &points[i], &points[(i + 1) % points.len()]) < 1e-3);
+            if near_edge {
+                continue;
+            }
+
+            let old = old_intersect(&points, &src, &ray);
+            let new = polygon.intersect(&src, &ray, 0.0);
+            assert_eq!(old.is_some(), new.is_some(),
+                "{:?} from {:?} along {:?}", points, src, ray);
+            if let (Some(old), Some(new)) = (old, new) {
+                assert!((old - new.dist).abs() <= 1e-4 * old);
+                hits += 1;
+            }
         }
+        assert!(hits > 300);
+    }
 
-        // Bilinearly interpolate between the nearest forward and reverse
-        // edges.  Both should always be found for well-defined polygons.
-        match (nearest_forward_dist, nearest_reverse_dist) {
-            (Some(forward_dist), Some(reverse_dist)) => {
-                let fna = &self.vertices[nearest_forward_index].normal;
-                let fnb = &self.vertices[(nearest_forward_index + 1) %
-                        self.vertices.len()].normal;
-                let forward_normal = vector_math::interpolate(
-                    fna, fnb, nearest_forward_scale).normalized();
-
-                let rna = &self.vertices[nearest_reverse_index].normal;
-                let rnb = &self.vertices[(nearest_reverse_index + 1) %
-                        self.vertices.len()].normal;
-                let reverse_normal = vector_math::interpolate(
-                    rna, rnb, nearest_reverse_scale).normalized();
-
-                // Remember reverse_dist is negative, forward_dist is positive.
-                let scale = reverse_dist / (reverse_dist - forward_dist);
-                let normal = vector_math::interpolate(
-                    &forward_normal, &reverse_normal, scale).normalized();
-
-                Some(IntersectResult {
-                    normal: normal,
-                    dist: src_to_plane_dist
+    #[test]
+    fn concave_polygon() {
+        // A U shape open at the top
+        let polygon = flat(&[(0.0, 0.0), (3.0, 0.0), (3.0, 3.0), (2.0, 3.0),
+            (2.0, 1.0), (1.0, 1.0), (1.0, 3.0), (0.0, 3.0)]);
+        for &(x, y) in &[(0.5, 2.0), (2.5, 2.0), (1.5, 0.5), (0.5, 0.5),
+                (0.5, 1.0), (2.5, 1.0)] {
+            let hit = hit(&polygon, x, y).expect("inside");
+            assert_eq!(hit.dist, 1.0);
+            assert_close(&hit.normal, &vector(0.0, 0.0, 1.0));
+        }
+        for &(x, y) in &[(1.5, 2.0), (1.5, 1.5), (-0.5, 1.0), (3.5, 1.0),
+                (1.5, 3.5)] {
+            assert!(hit(&polygon, x, y).is_none(), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn shared_edges_and_vertices_are_hit_once() {
+        // Four unit squares around the origin
+        let squares: Vec<Polygon> = [(-1.0, -1.0), (0.0, -1.0), (0.0, 0.0),
+                (-1.0, 0.0)].iter()
+            .map(|&(x, y)| flat(&[(x, y), (x + 1.0, y), (x + 1.0, y + 1.0),
+                (x, y + 1.0)]))
+            .collect();
+        for &(x, y) in &[(0.0, 0.0), (0.0, 0.5), (0.0, -0.25), (0.5, 0.0),
+                (-0.75, 0.0)] {
+            let hits = squares.iter()
+                .filter(|square| hit(square, x, y).is_some())
+                .count();
+            assert_eq!(hits, 1, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn normals_on_edges_and_vertices() {
+        let normals = [vector(0.0, 0.0, 1.0), vector(1.0, 0.0, 1.0),
+            vector(1.0, 1.0, 1.0), vector(0.0, 1.0, 1.0)];
+        let polygon = Polygon::new(
+            [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].iter()
+                .zip(normals.iter())
+                .map(|(&(x, y), normal)| PointNormal {
+                    point: point(x, y, 0.0),
+                    normal: normal.clone()
                 })
+                .collect());
+        let plane = polygon.plane.as_ref().unwrap();
+        let normal_at = |x: f32, y: f32| polygon.interpolate_normal(
+            &plane.corners, plane.project(&vector(x, y, 0.0)));
+
+        assert_close(&normal_at(0.0, 0.0), &normals[0].normalized());
+        assert_close(&normal_at(1.0, 1.0), &normals[2].normalized());
+        assert_close(&normal_at(0.25, 0.0),
+            &vector_math::interpolate(&normals[0], &normals[1], 0.75));
+        assert_close(&normal_at(0.0, 0.75),
+            &vector_math::interpolate(&normals[3], &normals[0], 0.75));
+    }
+
+    #[test]
+    fn mean_value_normals_are_barycentric_on_triangles() {
+        let normals = [vector(1.0, 0.0, 0.0), vector(0.0, 1.0, 0.0),
+            vector(0.0, 0.0, 1.0)];
+        let corners = [(0.0, 0.0), (2.0, 0.0), (0.5, 1.5)];
+        let polygon = flat_polygon(&corners, |x, _| {
+            normals[corners.iter().position(|corner| corner.0 == x).unwrap()]
+                .clone()
+        });
+
+        for &[a, b, c] in &[[0.2, 0.3, 0.5], [0.6, 0.2, 0.2],
+                [0.1, 0.1, 0.8]] {
+            let x = a * corners[0].0 + b * corners[1].0 + c * corners[2].0;
+            let y = a * corners[0].1 + b * corners[1].1 + c * corners[2].1;
+            assert_close(&hit(&polygon, x, y).unwrap().normal,
+                &vector(a, b, c).normalized());
+        }
+    }
+
+    #[test]
+    fn mean_value_normals_reproduce_linear_functions() {
+        // Normals that vary linearly across the plane are interpolated
+        // exactly, for convex and concave polygons alike.
+        let linear = |x: f32, y: f32| vector(x, y, 1.0);
+        let hexagon = flat_polygon(&[(1.0, 0.0), (0.5, 0.9), (-0.5, 0.9),
+            (-1.0, 0.0), (-0.5, -0.9), (0.5, -0.9)], linear);
+        let u_shape = flat_polygon(&[(0.0, 0.0), (3.0, 0.0), (3.0, 3.0),
+            (2.0, 3.0), (2.0, 1.0), (1.0, 1.0), (1.0, 3.0), (0.0, 3.0)],
+            linear);
+
+        for (polygon, points) in [
+                (&hexagon, [(0.0, 0.0), (0.3, -0.4), (-0.7, 0.2)]),
+                (&u_shape, [(0.5, 2.5), (2.6, 1.2), (1.5, 0.5)])] {
+            for &(x, y) in &points {
+                assert_close(&hit(polygon, x, y).unwrap().normal,
+                    &linear(x, y).normalized());
             }
-            (_, _) => None
         }
     }
 }
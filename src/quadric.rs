@@ -0,0 +1,404 @@
+use crate::vector_math::{Point, Vector};
+use crate::shape::{Shape, IntersectResult, BoundingBox, Interval};
+use crate::cuboid::Cuboid;
+use crate::csg::{self, Operation};
+
+
+/// A general quadric surface: the points p = (x, y, z, 1) where
+///  p^T M p = 0
+/// for a symmetric 4x4 `matrix` M. Points where p^T M p is negative are
+/// inside the surface, and the normal points outwards, towards positive
+/// values.
+///
+/// If `clip` is given, only the part of the surface inside that box is
+/// kept, so open surfaces like paraboloids can be cut to a finite size. As a
+/// solid for CSG, it's then closed off by the faces of the box.
+pub struct Quadric {
+    /// Symmetric matrix M defining the surface
+    pub matrix: [[f32; 4]; 4],
+    /// Box to cut the surface to, or None to keep all of it
+    pub clip: Option<BoundingBox>
+}
+
+impl Quadric {
+    // A quadric of the form
+    //  a (x - x0)^2 + b (y - y0)^2 + c (z - z0)^2 + d (y - y0) + e = 0
+    // for `center` (x0, y0, z0), which covers all of the standard shapes
+    // below with their axes along y.
+    fn axis_aligned(center: &Point, squares: [f32; 3], linear_y: f32,
+            constant: f32) -> Quadric {
+        let [a, b, c] = squares;
+        let (x0, y0, z0) = (center.x, center.y, center.z);
+        let half_x = -a * x0;
+        let half_y = -b * y0 + linear_y / 2.0;
+        let half_z = -c * z0;
+        let constant = a * x0 * x0 + b * y0 * y0 + c * z0 * z0 -
+            linear_y * y0 + constant;
+
+        Quadric {
+            matrix: [
+                [a, 0.0, 0.0, half_x],
+                [0.0, b, 0.0, half_y],
+                [0.0, 0.0, c, half_z],
+                [half_x, half_y, half_z, constant]],
+            clip: None
+        }
+    }
+
+    /// Ellipsoid with the given radii along the x, y and z axes
+    pub fn ellipsoid(center: &Point, radii: &Vector) -> Quadric {
+        Quadric::axis_aligned(center,
+            [1.0 / (radii.dx * radii.dx),
+                1.0 / (radii.dy * radii.dy),
+                1.0 / (radii.dz * radii.dz)],
+            0.0, -1.0)
+    }
+
+    /// Elliptic paraboloid with its tip at `vertex`, opening towards +y.
+    /// Its cross-section `radii.dy` above the tip has radii `radii.dx` and
+    /// `radii.dz` along x and z.
+    pub fn elliptic_paraboloid(vertex: &Point, radii: &Vector) -> Quadric {
+        Quadric::axis_aligned(vertex,
+            [1.0 / (radii.dx * radii.dx), 0.0, 1.0 / (radii.dz * radii.dz)],
+            -1.0 / radii.dy, 0.0)
+    }
+
+    /// Hyperboloid of one sheet around the y axis, with a waist of radii
+    /// `radii.dx` and `radii.dz` at the center, widening by those radii
+    /// again every `radii.dy` up or down
+    pub fn hyperboloid(center: &Point, radii: &Vector) -> Quadric {
+        Quadric::axis_aligned(center,
+            [1.0 / (radii.dx * radii.dx),
+                -1.0 / (radii.dy * radii.dy),
+                1.0 / (radii.dz * radii.dz)],
+            0.0, -1.0)
+    }
+
+    /// Hyperboloid of two sheets, opening towards +y and -y, with its tips
+    /// `radii.dy` above and below the center. The asymptotic cones widen by
+    /// `radii.dx` and `radii.dz` every `radii.dy` along y.
+    pub fn two_sheet_hyperboloid(center: &Point, radii: &Vector) -> Quadric {
+        Quadric::axis_aligned(center,
+            [1.0 / (radii.dx * radii.dx),
+                -1.0 / (radii.dy * radii.dy),
+                1.0 / (radii.dz * radii.dz)],
+            0.0, 1.0)
+    }
+
+    /// Keep only the part of the surface inside `bounds`.
+    pub fn clipped(self, bounds: BoundingBox) -> Quadric {
+        Quadric {
+            clip: Some(bounds),
+            ..self
+        }
+    }
+
+    // M p for the homogeneous point (x, y, z, w). This is done in double
+    // precision, since the constant term of a quadric away from the origin
+    // is large, and mostly cancels out near the surface.
+    fn transform(&self, p: [f64; 4]) -> [f64; 4] {
+        let mut result = [0.0; 4];
+        for (row, value) in self.matrix.iter().zip(result.iter_mut()) {
+            *value = row[0] as f64 * p[0] + row[1] as f64 * p[1] +
+                row[2] as f64 * p[2] + row[3] as f64 * p[3];
+        }
+        result
+    }
+
+    // Box enclosing the surface itself, if it's an ellipsoid; every other
+    // kind of quadric is unbounded.
+    fn ellipsoid_bounds(&self) -> Option<BoundingBox> {
+        // Writing the quadric as x^T A x + 2 b^T x + c for the upper left
+        // 3x3 block A, it's an ellipsoid if A is positive or negative
+        // definite. It's centered on x0 = -A^-1 b, and reaches out along
+        // axis i by sqrt(-f(x0) (A^-1)_ii).
+        let m = self.matrix.map(|row| row.map(|value| value as f64));
+        let minor = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        let adjugate = [
+            [m[1][1] * m[2][2] - m[1][2] * m[2][1],
+                m[0][2] * m[2][1] - m[0][1] * m[2][2],
+                m[0][1] * m[1][2] - m[0][2] * m[1][1]],
+            [m[1][2] * m[2][0] - m[1][0] * m[2][2],
+                m[0][0] * m[2][2] - m[0][2] * m[2][0],
+                m[0][2] * m[1][0] - m[0][0] * m[1][2]],
+            [m[1][0] * m[2][1] - m[1][1] * m[2][0],
+                m[0][1] * m[2][0] - m[0][0] * m[2][1],
+                m[0][0] * m[1][1] - m[0][1] * m[1][0]]];
+        let determinant = m[0][0] * adjugate[0][0] +
+            m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+
+        let positive_definite =
+            m[0][0] > 0.0 && minor > 0.0 && determinant > 0.0;
+        let negative_definite =
+            m[0][0] < 0.0 && minor > 0.0 && determinant < 0.0;
+        if !positive_definite && !negative_definite {
+            return None;
+        }
+
+        let inverse = |i: usize, j: usize| adjugate[i][j] / determinant;
+        let b = [m[0][3], m[1][3], m[2][3]];
+        let mut center = [0.0f64; 3];
+        for (i, value) in center.iter_mut().enumerate() {
+            *value = -(inverse(i, 0) * b[0] + inverse(i, 1) * b[1] +
+                inverse(i, 2) * b[2]);
+        }
+        let value_at_center = self.transform(
+            [center[0], center[1], center[2], 1.0])[3];
+
+        // An empty quadric has no points, so give it an empty box at its
+        // center.
+        let reach = |i: usize|
+            (-value_at_center * inverse(i, i)).max(0.0).sqrt();
+        let corner = |i: usize| (center[i] - reach(i)) as f32;
+        let extent = |i: usize| (center[i] + reach(i)) as f32 - corner(i);
+        Some(BoundingBox {
+            corner: Point {
+                x: corner(0),
+                y: corner(1),
+                z: corner(2)
+            },
+            extent: Vector {
+                dx: extent(0),
+                dy: extent(1),
+                dz: extent(2)
+            }
+        })
+    }
+
+    // Coefficients of the quadratic a t^2 + 2 half_b t + c that's p^T M p
+    // at the point t along `line`. With the direction having w = 0, it's
+    //  [ray^T M ray] t^2 + 2 [ray^T M src] t + src^T M src
+    fn coefficients(&self, line: &Line) -> (f64, f64, f64) {
+        let m_src = self.transform(line.src);
+        let m_ray = self.transform(line.ray);
+        let dot = |u: &[f64; 4], v: &[f64; 4]|
+            u[0] * v[0] + u[1] * v[1] + u[2] * v[2] + u[3] * v[3];
+        (dot(&line.ray, &m_ray), dot(&line.ray, &m_src),
+            dot(&line.src, &m_src))
+    }
+
+    // Where the quadratic is zero, in order, or None if it never is. This is
+    // done in a form that doesn't subtract nearly equal numbers, and that
+    // still works when the ray runs parallel to an asymptote or the axis of
+    // a paraboloid, so a is zero and there's only one root (followed by
+    // infinity).
+    fn roots(a: f64, half_b: f64, c: f64) -> Option<[f64; 2]> {
+        if a == 0.0 {
+            if half_b == 0.0 {
+                return None;
+            }
+            return Some([-c / (2.0 * half_b), f64::INFINITY]);
+        }
+
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let q = -(half_b + discriminant.sqrt().copysign(half_b));
+        let (r1, r2) = if q == 0.0 {
+            (0.0, 0.0)
+        } else {
+            (q / a, c / q)
+        };
+        Some([r1.min(r2), r1.max(r2)])
+    }
+
+    // The surface at `root` along `line`, with the normal along the
+    // gradient, towards positive values
+    fn hit(&self, line: &Line, root: f64) -> IntersectResult {
+        let gradient = self.transform([
+            line.src[0] + line.ray[0] * root,
+            line.src[1] + line.ray[1] * root,
+            line.src[2] + line.ray[2] * root,
+            1.0]);
+        IntersectResult {
+            normal: Vector {
+                dx: gradient[0] as f32,
+                dy: gradient[1] as f32,
+                dz: gradient[2] as f32
+            }.normalized(),
+            dist: root as f32
+        }
+    }
+
+    fn in_clip(&self, point: &Point) -> bool {
+        match &self.clip {
+            Some(clip) => {
+                let max = clip.max_corner();
+                point.x >= clip.corner.x && point.x <= max.x &&
+                    point.y >= clip.corner.y && point.y <= max.y &&
+                    point.z >= clip.corner.z && point.z <= max.z
+            },
+            None => true
+        }
+    }
+}
+
+// A ray in homogeneous coordinates, in double precision
+struct Line {
+    src: [f64; 4],
+    ray: [f64; 4]
+}
+
+impl Line {
+    fn new(src: &Point, ray: &Vector) -> Line {
+        Line {
+            src: [src.x as f64, src.y as f64, src.z as f64, 1.0],
+            ray: [ray.dx as f64, ray.dy as f64, ray.dz as f64, 0.0]
+        }
+    }
+}
+
+impl Shape for Quadric {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        match (self.ellipsoid_bounds(), &self.clip) {
+            // Only the overlap of the two boxes can be hit.
+            (Some(bounds), Some(clip)) => Some(bounds.overlap(clip)),
+            (Some(bounds), None) => Some(bounds),
+            (None, clip) => clip.clone()
+        }
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        let line = Line::new(src, ray);
+        let (a, half_b, c) = self.coefficients(&line);
+        let roots = Quadric::roots(a, half_b, c)?;
+
+        for &root in &roots {
+            let dist = root as f32;
+            if dist < near || !root.is_finite() {
+                continue;
+            }
+
+            let point = src + ray * dist;
+            if !self.in_clip(&point) {
+                continue;
+            }
+
+            return Some(self.hit(&line, root));
+        }
+
+        None
+    }
+
+    // The solid is where p^T M p is negative, which along the line is
+    // wherever the quadratic is. If the surface is clipped, so is the
+    // solid, and the faces of the clipping box close it off.
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        let line = Line::new(src, ray);
+        let (a, half_b, c) = self.coefficients(&line);
+        let end = |root: f64| if root.is_finite() {
+            self.hit(&line, root)
+        } else {
+            IntersectResult {
+                normal: Vector {dx: 0.0, dy: 0.0, dz: 0.0},
+                dist: root as f32
+            }
+        };
+        let interval = |enter: f64, exit: f64| Interval {
+            enter: end(enter),
+            exit: end(exit)
+        };
+
+        let (before, after) = (f64::NEG_INFINITY, f64::INFINITY);
+        let intervals = match Quadric::roots(a, half_b, c) {
+            // The quadratic has the same sign everywhere along the line.
+            None => {
+                let inside = if a == 0.0 {c < 0.0} else {a < 0.0};
+                if inside {vec![interval(before, after)]} else {vec![]}
+            },
+            // Only a linear term, so the line crosses the surface once
+            Some([root, _]) if a == 0.0 => if half_b > 0.0 {
+                vec![interval(before, root)]
+            } else {
+                vec![interval(root, after)]
+            },
+            Some([r1, r2]) => if a > 0.0 {
+                vec![interval(r1, r2)]
+            } else {
+                vec![interval(before, r1), interval(r2, after)]
+            }
+        };
+
+        Some(match &self.clip {
+            Some(clip) => {
+                let half_size = &clip.extent * 0.5;
+                let clip = Cuboid {
+                    center: &clip.corner + &half_size,
+                    half_size,
+                    orientation: None
+                };
+                csg::combine(Operation::Intersection, intervals,
+                    csg::convex_intervals(&clip, src, ray))
+            },
+            None => intervals
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {x, y, z}
+    }
+
+    fn ends(quadric: &Quadric, src: &Point, ray: &Vector) -> Vec<(f32, f32)> {
+        quadric.intervals(src, ray).unwrap().iter()
+            .map(|interval| (interval.enter.dist, interval.exit.dist))
+            .collect()
+    }
+
+    #[test]
+    fn intervals() {
+        let along_x = Vector {dx: 1.0, dy: 0.0, dz: 0.0};
+        let along_y = Vector {dx: 0.0, dy: 1.0, dz: 0.0};
+        let along_z = Vector {dx: 0.0, dy: 0.0, dz: 1.0};
+        let radii = Vector {dx: 1.0, dy: 2.0, dz: 3.0};
+        let ellipsoid = Quadric::ellipsoid(&point(0.0, 0.0, 0.0), &radii);
+        assert_eq!(ends(&ellipsoid, &point(0.0, 0.0, -5.0), &along_z),
+            [(2.0, 8.0)]);
+        assert_eq!(ends(&ellipsoid, &point(0.0, 5.0, -5.0), &along_z), []);
+
+        // The hyperboloid's solid is the inside of its waist, which runs on
+        // forever along its axis and out to either side of the surface.
+        let unit = Vector {dx: 1.0, dy: 1.0, dz: 1.0};
+        let hyperboloid = Quadric::hyperboloid(&point(0.0, 0.0, 0.0), &unit);
+        assert_eq!(ends(&hyperboloid, &point(-5.0, 0.0, 0.0), &along_x),
+            [(4.0, 6.0)]);
+        assert_eq!(ends(&hyperboloid, &point(0.0, -5.0, 0.0), &along_y),
+            [(f32::NEG_INFINITY, f32::INFINITY)]);
+        let root_3 = 3.0f32.sqrt();
+        assert_eq!(ends(&hyperboloid, &point(2.0, -5.0, 0.0), &along_y),
+            [(f32::NEG_INFINITY, 5.0 - root_3),
+                (5.0 + root_3, f32::INFINITY)]);
+
+        // A paraboloid along its axis crosses the surface once.
+        let paraboloid =
+            Quadric::elliptic_paraboloid(&point(0.0, 0.0, 0.0), &unit);
+        assert_eq!(ends(&paraboloid, &point(0.0, -5.0, 0.0), &along_y),
+            [(5.0, f32::INFINITY)]);
+    }
+
+    #[test]
+    fn clipped_intervals() {
+        let radii = Vector {dx: 1.0, dy: 2.0, dz: 3.0};
+        let clip = BoundingBox {
+            corner: point(-5.0, -5.0, -1.0),
+            extent: Vector {dx: 10.0, dy: 10.0, dz: 2.0}
+        };
+        let ellipsoid =
+            Quadric::ellipsoid(&point(0.0, 0.0, 0.0), &radii).clipped(clip);
+        let intervals = ellipsoid.intervals(&point(0.0, 0.0, -5.0),
+            &Vector {dx: 0.0, dy: 0.0, dz: 1.0}).unwrap();
+
+        // It's closed off by the faces of the box.
+        assert_eq!(intervals.len(), 1);
+        assert_eq!(intervals[0].enter.dist, 4.0);
+        assert_eq!(intervals[0].enter.normal.dz, -1.0);
+        assert_eq!(intervals[0].exit.dist, 6.0);
+        assert_eq!(intervals[0].exit.normal.dz, 1.0);
+    }
+}
@@ -1,114 +1,805 @@
-use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use crate::vector_math;
 use crate::vector_math::{Vector, Point};
 use crate::scene::Scene;
 use crate::color::Color;
 use crate::shape::Shape;
+use crate::space_partition::SpacePartition;
+use crate::sampling::{Rng, SamplePattern};
+use crate::filter::Filter;
+use crate::camera::{Camera, Projection};
 
+/// The camera: where the scene is viewed from, and the size and shape of
+/// the image.
+#[derive(Debug, Clone)]
 pub struct View {
-    // Position in space of the viewer
+    /// Position in space of the viewer
     pub from: Point,
-    // Position in space that will be visible at the center of the view
+    /// Position in space that will be visible at the center of the view
     pub at: Point,
-    // Orientation of the view in space
+    /// Orientation of the view in space
     pub up: Vector,
-    // Field of view angle in degrees (vertical)
+    /// Field of view angle in degrees (vertical)
     pub angle: f32,
-    // Near-clip distance; objects in space closer to the viewer are invisible
+    /// Near-clip distance; objects in space closer to the viewer are invisible
     pub hither: f32,
-    // Width of the view in pixels
+    /// Far-clip distance; if set, objects in space farther from the viewer
+    /// are invisible
+    pub yon: Option<f32>,
+    /// Rotation of the camera about the view direction in degrees,
+    /// clockwise as seen by the viewer; the scene appears rotated the
+    /// opposite way
+    pub roll: f32,
+    /// Width of the view in pixels
     pub width: u32,
-    // Height of the view in pixels
-    pub height: u32
+    /// Height of the view in pixels
+    pub height: u32,
+    /// Radius of the camera lens; 0.0 for a pinhole camera, where everything
+    /// is in focus
+    pub aperture: f32,
+    /// Distance from the viewer to the plane that's in perfect focus. If
+    /// unset, the camera focuses on `at`.
+    pub focal_distance: Option<f32>,
+    /// Mapping of the image onto directions from the viewer
+    pub projection: Projection
 }
 
 impl View {
+    /// Pinhole perspective view with no far clipping or roll.
+    pub fn new(from: Point,
+            at: Point,
+            up: Vector,
+            angle: f32,
+            hither: f32,
+            width: u32,
+            height: u32) -> View {
+        View {
+            from,
+            at,
+            up,
+            angle,
+            hither,
+            yon: None,
+            roll: 0.0,
+            width,
+            height,
+            aperture: 0.0,
+            focal_distance: None,
+            projection: Projection::Perspective
+        }
+    }
+
+    /// Change the size of the view in pixels. The field of view angle is
+    /// vertical, so the view covers the same height in the scene; if the
+    /// aspect ratio changes, more or less is seen at the sides.
+    pub fn set_resolution(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Width of the view divided by its height
     pub fn aspect_ratio(&self) -> f32 {
         (self.width as f32) / (self.height as f32)
     }
+
+    /// Orientation of the view, with the roll applied. This is only
+    /// perpendicular to the view direction if the roll is non-zero.
+    pub fn rolled_up(&self) -> Vector {
+        if self.roll == 0.0 {
+            return self.up.clone();
+        }
+
+        let forward = (&self.at - &self.from).normalized();
+        let right = vector_math::cross(&forward, &self.up).normalized();
+        let up = vector_math::cross(&right, &forward);
+        let (sin, cos) = (std::f32::consts::PI * self.roll / 180.0).sin_cos();
+        up * cos + right * sin
+    }
+
+    /// Distance from the viewer to the plane that's in perfect focus.
+    pub fn focus(&self) -> f32 {
+        self.focal_distance.unwrap_or_else(
+            || (&self.at - &self.from).magnitude())
+    }
 }
 
-pub trait Surface {
+/// How light interacts with the surface of a shape.
+pub trait Surface: Send + Sync {
+    /// Color seen along the ray `view` where it hits the surface with
+    /// `normal`, lit by a light of `light_color` in `light_direction` from
+    /// the hit. Reflected and transmitted light is added by the tracer.
     fn get_visible_color(&self,
         normal: &Vector,
         view: &Vector,
         light_direction: &Vector,
         light_color: &Color) -> Color;
 
+    /// Fraction of the light reflected as a mirror image of the scene
     fn get_reflectance(&self) -> f32;
 
+    /// Fraction of the light passing through the surface
     fn get_transmittance(&self) -> f32;
 
+    /// Index of refraction for light passing through
     fn get_refraction_index(&self) -> f32;
 }
 
+/// A shape in the scene and the surface it's drawn with.
 pub struct Primitive {
+    /// Geometry of the primitive
     pub shape: Box<dyn Shape>,
-    pub surface: Rc<dyn Surface>
+    /// Shading of the primitive
+    pub surface: Arc<dyn Surface>
+}
+
+// Width and height of the square tiles the image is divided into for
+// rendering; each tile is rendered start to finish by a single thread.
+const TILE_SIZE: usize = 32;
+
+/// Settings for `render`. The defaults trace one ray through the center of
+/// each pixel, with a thread for each CPU.
+pub struct RenderOptions {
+    /// Number of threads to render with
+    pub threads: usize,
+    /// Number of rays to trace through each pixel; their colors are averaged
+    pub samples: usize,
+    /// Arrangement of the rays within each pixel
+    pub pattern: SamplePattern,
+    /// Seed for the random number generators used by randomized sampling
+    pub seed: u64,
+    /// If set, pixels are sampled adaptively instead, and `samples` and
+    /// `pattern` are ignored.
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Filter used to reconstruct pixel values from the samples
+    pub filter: Filter,
+    /// If set, only this part of the image is rendered; the rest is left
+    /// black and transparent.
+    pub crop: Option<Crop>,
+    /// If set, called as each stage of the render starts. It may be called
+    /// from several threads at once.
+    pub progress: Option<Box<dyn Fn(Progress) + Send + Sync>>
+}
+
+/// Stages of a render, as reported to `RenderOptions::progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// Building the space partition of the scene's primitives
+    BuildingSpacePartition,
+    /// Rendering one of the tiles the image is divided into; tiles may be
+    /// started out of order
+    Tile {
+        /// Index of the tile, counting from 0
+        index: usize,
+        /// Number of tiles in the image
+        count: usize
+    },
+    /// Starting on the left eye of a stereo pair, whose stages are reported
+    /// in turn
+    LeftEye,
+    /// Starting on the right eye of a stereo pair
+    RightEye
+}
+
+impl RenderOptions {
+    pub(crate) fn report(&self, progress: Progress) {
+        if let Some(callback) = &self.progress {
+            callback(progress);
+        }
+    }
+}
+
+/// A rectangular region of the image to render, in pixels from the top left
+/// corner.
+#[derive(Debug, Clone)]
+pub struct Crop {
+    /// Left edge of the region
+    pub x: usize,
+    /// Top edge of the region
+    pub y: usize,
+    /// Width of the region
+    pub width: usize,
+    /// Height of the region
+    pub height: usize
+}
+
+/// Settings for adaptive anti-aliasing, in the style of Whitted's original
+/// ray tracer: each pixel is sampled at its four corners, and any region of
+/// the pixel whose corners differ too much is split into four quadrants,
+/// which are sampled at their corners in turn.
+#[derive(Debug, Clone)]
+pub struct AdaptiveSampling {
+    /// Largest difference allowed in any color channel or alpha between the
+    /// corners of a region before it's subdivided
+    pub threshold: f32,
+    /// Maximum number of times a pixel can be subdivided
+    pub max_depth: u32
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            samples: 1,
+            pattern: SamplePattern::Grid,
+            seed: 0,
+            adaptive: None,
+            filter: Filter::default(),
+            crop: None,
+            progress: None
+        }
+    }
+}
+
+// A rectangular region of the render target, in pixels.
+#[derive(Debug, Clone)]
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize
 }
 
+/// An image being rendered, with the color, coverage and number of rays
+/// traced for each pixel.
 pub struct RenderTarget {
+    /// Width of the image in pixels
     pub width: usize,
+    /// Height of the image in pixels
     pub height: usize,
-    values: Vec<Color>
+    values: Vec<Color>,
+    // Coverage of each pixel, from 0.0 (background only) to 1.0 (fully
+    // covered by objects in the scene)
+    alphas: Vec<f32>,
+    // Number of rays traced for each pixel
+    sample_counts: Vec<u32>,
+    // Filter-weighted sums of the colors and alphas of the samples
+    // contributing to each pixel, and the sums of their weights. The pixel
+    // values are the weighted averages.
+    color_sums: Vec<Color>,
+    alpha_sums: Vec<f32>,
+    weights: Vec<f32>
 }
 
 impl RenderTarget {
+    /// Image of the given size, black and transparent.
     pub fn new(width: usize, height: usize) -> RenderTarget {
         let mut values = Vec::new();
         values.resize(width * height, Color {r: 0.0, g: 0.0, b: 0.0});
-        RenderTarget {width, height, values}
+        RenderTarget {
+            width,
+            height,
+            color_sums: values.clone(),
+            values,
+            alphas: vec![0.0; width * height],
+            sample_counts: vec![0; width * height],
+            alpha_sums: vec![0.0; width * height],
+            weights: vec![0.0; width * height]
+        }
     }
 
+    /// Set a pixel to a fully opaque color.
     pub fn set(&mut self, x: usize, y: usize, color: Color) {
-        self.values[y * self.width + x] = color;
+        self.set_with_alpha(x, y, color, 1.0);
+    }
+
+    /// Set a pixel's color and alpha, replacing any samples that have been
+    /// added to it.
+    pub fn set_with_alpha(&mut self,
+            x: usize, y: usize, color: Color, alpha: f32) {
+        let index = y * self.width + x;
+        self.color_sums[index] = color.clone();
+        self.alpha_sums[index] = alpha;
+        self.weights[index] = 1.0;
+        self.values[index] = color;
+        self.alphas[index] = alpha;
+    }
+
+    /// Add a sample at the point (`x`, `y`) on the image, measured in pixels
+    /// from the top left corner, to every pixel within the radius of
+    /// `filter`. Samples near the edges may contribute to pixels outside the
+    /// image, which are skipped.
+    pub fn add_sample(&mut self,
+            x: f32, y: f32, color: &Color, alpha: f32, filter: &Filter) {
+        self.add_weighted_sample(x, y, color, alpha, filter, 1.0);
+    }
+
+    // Like `add_sample`, but with the filter weights scaled by `scale`, for
+    // a sample that stands for only part of a pixel.
+    pub(crate) fn add_weighted_sample(&mut self,
+            x: f32,
+            y: f32,
+            color: &Color,
+            alpha: f32,
+            filter: &Filter,
+            scale: f32) {
+        let (first_i, last_i) = filter.pixel_range(x);
+        let (first_j, last_j) = filter.pixel_range(y);
+
+        for j in first_j.max(0)..=last_j.min(self.height as isize - 1) {
+            for i in first_i.max(0)..=last_i.min(self.width as isize - 1) {
+                let weight = scale * filter.weight(
+                    x - (i as f32 + 0.5), y - (j as f32 + 0.5));
+                self.accumulate(i as usize, j as usize,
+                    &(color * weight), alpha * weight, weight);
+            }
+        }
+    }
+
+    // Add weighted sums of samples to a pixel and update its value.
+    fn accumulate(&mut self,
+            x: usize,
+            y: usize,
+            color_sum: &Color,
+            alpha_sum: f32,
+            weight: f32) {
+        let index = y * self.width + x;
+        self.color_sums[index] += color_sum;
+        self.alpha_sums[index] += alpha_sum;
+        self.weights[index] += weight;
+
+        // Filters with negative lobes can leave pixels with no net weight;
+        // those keep their last value.
+        let total_weight = self.weights[index];
+        if total_weight.abs() > 1e-6 {
+            self.values[index] = &self.color_sums[index] / total_weight;
+            self.alphas[index] = self.alpha_sums[index] / total_weight;
+        }
+    }
+
+    // Add all the samples from `other` to this image, with the top left
+    // corner of `other` placed at (`x`, `y`).
+    fn merge(&mut self, other: &RenderTarget, x: usize, y: usize) {
+        for j in 0..other.height {
+            for i in 0..other.width {
+                let index = j * other.width + i;
+                self.accumulate(x + i, y + j, &other.color_sums[index],
+                    other.alpha_sums[index], other.weights[index]);
+                self.sample_counts[(y + j) * self.width + x + i] +=
+                    other.sample_counts[index];
+            }
+        }
     }
 
+    /// Color of a pixel
     pub fn get(&self, x: usize, y: usize) -> &Color {
         &self.values[y * self.width + x]
     }
+
+    /// Coverage of a pixel, from 0.0 (background only) to 1.0 (fully
+    /// covered by objects in the scene)
+    pub fn get_alpha(&self, x: usize, y: usize) -> f32 {
+        self.alphas[y * self.width + x]
+    }
+
+    /// Set the number of rays recorded as traced for a pixel.
+    pub fn set_sample_count(&mut self, x: usize, y: usize, count: u32) {
+        self.sample_counts[y * self.width + x] = count;
+    }
+
+    /// Number of rays traced for a pixel
+    pub fn get_sample_count(&self, x: usize, y: usize) -> u32 {
+        self.sample_counts[y * self.width + x]
+    }
+
+    /// All pixel colors in scanline order, starting from the top left.
+    pub fn pixels(&self) -> &[Color] {
+        &self.values
+    }
+
+    /// Create a grayscale image showing the number of rays traced for each
+    /// pixel, scaled so the most heavily sampled pixels are white.
+    pub fn sample_count_image(&self) -> RenderTarget {
+        let max_count = self.sample_counts.iter().copied().max().unwrap_or(0);
+        let mut image = RenderTarget::new(self.width, self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let level = if max_count > 0 {
+                    self.get_sample_count(i, j) as f32 / max_count as f32
+                } else {
+                    0.0
+                };
+                image.set(i, j, Color {r: level, g: level, b: level});
+                image.set_sample_count(i, j, self.get_sample_count(i, j));
+            }
+        }
+        image
+    }
+}
+
+// Part of a `width` x `height` image to render, given the crop settings in
+// `options`.
+fn render_region(options: &RenderOptions, width: usize, height: usize) ->
+        Tile {
+    match &options.crop {
+        Some(crop) => {
+            let x = crop.x.min(width);
+            let y = crop.y.min(height);
+            Tile {
+                x,
+                y,
+                width: crop.width.min(width - x),
+                height: crop.height.min(height - y)
+            }
+        },
+        None => Tile {x: 0, y: 0, width, height}
+    }
 }
 
-pub fn render(view: &View, scene: &Scene, target: &mut RenderTarget) {
-    // All the rays can be thought of as passing through a rectangular screen
-    // that is <near> away from the eye, with dimensions:
-    //      width:  aspect ratio * near * tan(fov/2)
-    //      height: near * tan(fov/2).
-    // Since we're normalizing the rays anyway, we can assume <near> is
-    // a distance of 1.0 in the direction <at> - <from>.
+// Split a region of the image into tiles, in scanline order.
+fn make_tiles(region: &Tile) -> Vec<Tile> {
+    let right = region.x + region.width;
+    let bottom = region.y + region.height;
 
-    // Distance from the center of the screen to either edge
-    let up_len = (std::f32::consts::PI * view.angle / 360.0).tan();
-    let right_len = up_len * view.aspect_ratio();
+    let mut tiles = Vec::new();
+    for y in (region.y..bottom).step_by(TILE_SIZE) {
+        for x in (region.x..right).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(right - x),
+                height: TILE_SIZE.min(bottom - y)
+            });
+        }
+    }
+    tiles
+}
 
-    // Vector from the eye to the center of the screen.
-    let forward = (&view.at - &view.from).normalized();
+// The color and alpha seen by a single ray
+type Sample = (Color, f32);
 
-    // Vector from the center of the screen to the right edge.
-    let right = vector_math::cross(&forward, &view.up).normalized() * right_len;
+// Accumulates the samples for one tile of the image. Filters can spread
+// samples past the edges of the tile, so the film covers the tile plus a
+// margin as wide as the filter radius, clipped to the region being
+// rendered.
+struct TileFilm {
+    // Position of the film's top left corner in the image
+    x: usize,
+    y: usize,
+    image: RenderTarget
+}
 
-    // Vector from the center of the screen to the top edge; note view.up may
-    // not be perpendicular to forward, but this is.
-    let up = vector_math::cross(&right, &forward).normalized() * up_len;
+impl TileFilm {
+    fn new(tile: &Tile, filter: &Filter, region: &Tile) -> TileFilm {
+        let margin = filter.radius.ceil() as usize;
+        let x = tile.x.saturating_sub(margin).max(region.x);
+        let y = tile.y.saturating_sub(margin).max(region.y);
+        let right = (tile.x + tile.width + margin).min(region.x + region.width);
+        let bottom =
+            (tile.y + tile.height + margin).min(region.y + region.height);
 
-    println!("Building space partition");
-    let space_partition = scene.build_space_partition();
+        TileFilm {
+            x,
+            y,
+            image: RenderTarget::new(right - x, bottom - y)
+        }
+    }
+
+    // Add a sample at the point (`x`, `y`) on the full image.
+    fn add_sample(&mut self, x: f32, y: f32, sample: &Sample, filter: &Filter) {
+        self.add_weighted_sample(x, y, sample, filter, 1.0);
+    }
+
+    // Add a sample standing for `scale` of a pixel's area at the point
+    // (`x`, `y`) on the full image.
+    fn add_weighted_sample(&mut self,
+            x: f32, y: f32, sample: &Sample, filter: &Filter, scale: f32) {
+        let (color, alpha) = sample;
+        self.image.add_weighted_sample(x - self.x as f32, y - self.y as f32,
+            color, *alpha, filter, scale);
+    }
+
+    // Record that `count` rays were traced for the pixel at (`i`, `j`) on
+    // the full image.
+    fn add_sample_count(&mut self, i: usize, j: usize, count: u32) {
+        let index = (j - self.y) * self.image.width + (i - self.x);
+        self.image.sample_counts[index] += count;
+    }
+}
 
-    for j in 0..target.height {
-        println!("Rendering line {}", j + 1);
+// Traces rays through positions on the image.
+struct PixelSampler<'a> {
+    camera: &'a Camera,
+    scene: &'a Scene,
+    space_partition: &'a SpacePartition<'a>,
+    width: usize,
+    height: usize,
+    seed: u64
+}
 
+impl<'a> PixelSampler<'a> {
+    // Trace the ray through the point (`x`, `y`) on the image, measured in
+    // pixels from the top left corner.
+    fn sample(&self, x: f32, y: f32, rng: &mut Rng) -> Sample {
         // Convert to screen coordinates in the range [-1.0, 1.0]
-        let sy = 1.0 - ((2 * (j as isize) + 1) as f32) /
-            (target.height as f32);
+        let sx = -1.0 + 2.0 * x / (self.width as f32);
+        let sy = 1.0 - 2.0 * y / (self.height as f32);
+
+        // Points the projection doesn't cover are left transparent.
+        let (origin, ray) = match self.camera.ray(sx, sy, rng) {
+            Some(ray) => ray,
+            None => return (Color::black(), 0.0)
+        };
+        self.scene.trace_coverage(self.space_partition,
+            &origin, &ray, self.camera.hither(), self.camera.yon())
+    }
+
+    // Like `sample`, but with random numbers derived from the position on
+    // the image, for samples that don't belong to a single pixel.
+    fn sample_point(&self, x: f32, y: f32) -> Sample {
+        self.sample(x, y, &mut Rng::for_point(self.seed, x, y))
+    }
+
+    // Sample the pixel at (`i`, `j`) at each position of `options.pattern`
+    // and add the samples to `film`.
+    fn sample_uniform(&self,
+            i: usize,
+            j: usize,
+            options: &RenderOptions,
+            film: &mut TileFilm) {
+        let mut rng = Rng::for_pixel(options.seed, i, j);
+        let positions = options.pattern.generate(options.samples, &mut rng);
+
+        for (x, y) in &positions {
+            let (x, y) = (i as f32 + x, j as f32 + y);
+            let sample = self.sample(x, y, &mut rng);
+            film.add_sample(x, y, &sample, &options.filter);
+        }
+        film.add_sample_count(i, j, positions.len() as u32);
+    }
+
+    // Sample the square region of the image with its top left corner at
+    // (`x`, `y`), given the samples already taken at its corners, and add
+    // it to `film`. The region is split into quadrants and sampled
+    // recursively if the corners don't agree. Each region that isn't split
+    // is added as the average of its corners, placed at its center and
+    // weighted by its area, so `options.filter` spreads every part of the
+    // pixel from where it is. Returns the number of new samples taken.
+    #[allow(clippy::too_many_arguments)]
+    fn subdivide(&self,
+            x: f32,
+            y: f32,
+            size: f32,
+            corners: [&Sample; 4],
+            depth: u32,
+            settings: &AdaptiveSampling,
+            options: &RenderOptions,
+            film: &mut TileFilm) -> u32 {
+        if depth >= settings.max_depth ||
+                !exceeds_contrast(&corners, settings.threshold) {
+            let half = size / 2.0;
+            film.add_weighted_sample(x + half, y + half, &average(&corners),
+                &options.filter, size * size);
+            return 0;
+        }
+
+        let [top_left, top_right, bottom_left, bottom_right] = corners;
+        let half = size / 2.0;
+        let top = self.sample_point(x + half, y);
+        let left = self.sample_point(x, y + half);
+        let center = self.sample_point(x + half, y + half);
+        let right = self.sample_point(x + size, y + half);
+        let bottom = self.sample_point(x + half, y + size);
+
+        5 + self.subdivide(x, y, half,
+                [top_left, &top, &left, &center],
+                depth + 1, settings, options, film) +
+            self.subdivide(x + half, y, half,
+                [&top, top_right, &center, &right],
+                depth + 1, settings, options, film) +
+            self.subdivide(x, y + half, half,
+                [&left, &center, bottom_left, &bottom],
+                depth + 1, settings, options, film) +
+            self.subdivide(x + half, y + half, half,
+                [&center, &right, &bottom, bottom_right],
+                depth + 1, settings, options, film)
+    }
+}
+
+fn average(samples: &[&Sample]) -> Sample {
+    let mut total_color = Color::black();
+    let mut total_alpha = 0.0;
+    for (color, alpha) in samples {
+        total_color += color;
+        total_alpha += alpha;
+    }
+    let count = samples.len() as f32;
+    (total_color / count, total_alpha / count)
+}
+
+// Check whether any pair of `samples` differs by more than `threshold` in
+// any color channel or in alpha.
+fn exceeds_contrast(samples: &[&Sample], threshold: f32) -> bool {
+    for (a, (color_a, alpha_a)) in samples.iter().enumerate() {
+        for (color_b, alpha_b) in &samples[a + 1..] {
+            if (color_a.r - color_b.r).abs() > threshold ||
+                    (color_a.g - color_b.g).abs() > threshold ||
+                    (color_a.b - color_b.b).abs() > threshold ||
+                    (alpha_a - alpha_b).abs() > threshold {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Render the pixels covered by `tile`, which is part of `region`.
+fn render_tile(sampler: &PixelSampler,
+        options: &RenderOptions,
+        tile: &Tile,
+        region: &Tile) -> TileFilm {
+    let mut film = TileFilm::new(tile, &options.filter, region);
+
+    match &options.adaptive {
+        None => {
+            for j in tile.y..(tile.y + tile.height) {
+                for i in tile.x..(tile.x + tile.width) {
+                    sampler.sample_uniform(i, j, options, &mut film);
+                }
+            }
+        },
+        Some(settings) => {
+            // Sample all the pixel corners in the tile up front, since each
+            // one is shared by up to four pixels.
+            let corner_columns = tile.width + 1;
+            let mut corners = Vec::with_capacity(
+                corner_columns * (tile.height + 1));
+            for j in tile.y..=(tile.y + tile.height) {
+                for i in tile.x..=(tile.x + tile.width) {
+                    corners.push(sampler.sample_point(i as f32, j as f32));
+                }
+            }
+
+            for j in 0..tile.height {
+                for i in 0..tile.width {
+                    let top_left = j * corner_columns + i;
+                    let bottom_left = top_left + corner_columns;
+                    let (x, y) = (tile.x + i, tile.y + j);
+
+                    let sample_count = 4 + sampler.subdivide(
+                        x as f32, y as f32, 1.0,
+                        [&corners[top_left], &corners[top_left + 1],
+                            &corners[bottom_left], &corners[bottom_left + 1]],
+                        0, settings, options, &mut film);
+                    film.add_sample_count(x, y, sample_count);
+                }
+            }
+        }
+    }
+    film
+}
+
+/// Render `scene` as seen by `view` into `target`, which should be the size
+/// of the view.
+pub fn render(view: &View,
+        scene: &Scene,
+        target: &mut RenderTarget,
+        options: &RenderOptions) {
+    let camera = Camera::new(view);
+
+    options.report(Progress::BuildingSpacePartition);
+    let space_partition = scene.build_space_partition();
+
+    let region = render_region(options, target.width, target.height);
+    let tiles = make_tiles(&region);
+    let thread_count = options.threads.max(1).min(tiles.len().max(1));
+    let (width, height) = (target.width, target.height);
+
+    // Threads claim tiles in order from a shared counter until none are
+    // left. Each pixel is sampled independently, so the result doesn't
+    // depend on which thread renders which tile.
+    let next_tile = AtomicUsize::new(0);
+
+    let sampler = PixelSampler {
+        camera: &camera,
+        scene,
+        space_partition: &space_partition,
+        width,
+        height,
+        seed: options.seed
+    };
+
+    let mut rendered_tiles: Vec<(usize, TileFilm)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..thread_count).map(|_| {
+            scope.spawn(|| {
+                let mut rendered = Vec::new();
+                loop {
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() {
+                        break;
+                    }
+
+                    options.report(Progress::Tile {
+                        index,
+                        count: tiles.len()
+                    });
+                    let film = render_tile(
+                        &sampler, options, &tiles[index], &region);
+                    rendered.push((index, film));
+                }
+                rendered
+            })
+        }).collect();
+
+        workers.into_iter().flat_map(|worker| {
+            worker.join().expect("render thread panicked")
+        }).collect()
+    });
+
+    // Tiles can overlap once filtered, so merge them in a fixed order to
+    // keep the floating point sums the same from one render to the next.
+    rendered_tiles.sort_by_key(|(index, _)| *index);
+    for (_, film) in rendered_tiles {
+        target.merge(&film.image, film.x, film.y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterKind;
+    use crate::nff;
+
+    // Spheres over a plane, with enough edges and shading to show up any
+    // difference between renders
+    const SCENE: &str = "b 0.2 0.3 0.5
+v
+from 0 1 -6
+at 0 0 0
+up 0 1 0
+angle 40
+hither 0.1
+resolution 80 48
+l -4 6 -4
+l 3 2 -5 0.4 0.4 0.6
+f 1 0.2 0.2 0.8 0.2 10 0 0
+s -1 0 0 0.8
+s 0.9 0.3 0.5 0.6
+f 0.8 0.8 0.8 0.5 0.5 20 0.3 0
+s 0.1 -0.2 -1.2 0.4
+f 0.2 0.7 0.2 1 0 0 0 0
+plane 0 -0.8 0 0 1 0
+";
+
+    fn render_with_threads(threads: usize) -> RenderTarget {
+        let (view, scene) = nff::read_scene(&mut SCENE.as_bytes(), true)
+            .unwrap();
+        let view = view.unwrap();
+        let mut target = RenderTarget::new(80, 48);
+        render(&view, &scene, &mut target, &RenderOptions {
+            threads,
+            samples: 5,
+            pattern: SamplePattern::Jittered,
+            seed: 12345,
+            filter: Filter {kind: FilterKind::Gaussian, radius: 2.5},
+            ..RenderOptions::default()
+        });
+        target
+    }
 
-        for i in 0..target.width {
-            let sx = -1.0 + ((2 * (i as isize) + 1) as f32) /
-                (target.width as f32);
+    #[test]
+    fn same_result_with_any_number_of_threads() {
+        let single = render_with_threads(1);
+        let multiple = render_with_threads(4);
+        assert_eq!(make_tiles(&render_region(&RenderOptions::default(),
+            80, 48)).len(), 6);
 
-            let ray = (&forward + &up * sy + &right * sx).normalized();
-            let color = scene.trace(
-                &space_partition, &view.from, &ray, view.hither);
-            target.set(i, j, color);
+        let mut colors = std::collections::HashSet::new();
+        for j in 0..single.height {
+            for i in 0..single.width {
+                let (a, b) = (single.get(i, j), multiple.get(i, j));
+                let bits = [a.r.to_bits(), a.g.to_bits(), a.b.to_bits()];
+                assert_eq!(bits, [b.r.to_bits(), b.g.to_bits(), b.b.to_bits()],
+                    "pixel {}, {}", i, j);
+                assert_eq!(single.get_alpha(i, j).to_bits(),
+                    multiple.get_alpha(i, j).to_bits());
+                assert_eq!(single.get_sample_count(i, j),
+                    multiple.get_sample_count(i, j));
+                colors.insert(bits);
+            }
         }
+        // It's not just the background.
+        assert!(colors.len() > 100);
     }
 }
@@ -0,0 +1,123 @@
+// Small, fast pseudo-random number generator (SplitMix64). It isn't
+// cryptographically secure, but it's deterministic for a given seed, which
+// keeps renders reproducible.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng {state: seed}
+    }
+
+    // Create a generator for a single pixel. Every pixel gets its own
+    // sequence, so the samples don't depend on the order in which pixels are
+    // rendered or which thread renders them.
+    pub fn for_pixel(seed: u64, x: usize, y: usize) -> Rng {
+        // Scramble the seed before combining it with the pixel position, so
+        // different seeds don't just give the same sequences shifted to
+        // other pixels.
+        let scrambled_seed = Rng::new(seed).next_u64();
+        let mut rng =
+            Rng::new(scrambled_seed ^ (((x as u64) << 32) | y as u64));
+        rng.next_u64();
+        rng
+    }
+
+    // Create a generator for an arbitrary point on the image, for samples
+    // that aren't tied to a single pixel.
+    pub fn for_point(seed: u64, x: f32, y: f32) -> Rng {
+        Rng::for_pixel(seed, x.to_bits() as usize, y.to_bits() as usize)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniformly distributed value in [0.0, 1.0)
+    pub fn next_f32(&mut self) -> f32 {
+        // Use the top 24 bits, which is all the precision an f32 can hold.
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Uniformly distributed random point in the unit disk, as (x, y).
+pub fn unit_disk(rng: &mut Rng) -> (f32, f32) {
+    // Taking the square root of the radius keeps the points from bunching
+    // up in the middle.
+    let radius = rng.next_f32().sqrt();
+    let (sin, cos) = (2.0 * std::f32::consts::PI * rng.next_f32()).sin_cos();
+    (radius * cos, radius * sin)
+}
+
+/// Arrangements of sample positions within a pixel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplePattern {
+    /// Samples at the centers of a regular grid of sub-pixel cells
+    Grid,
+    /// One sample at a random position within each grid cell (stratified)
+    Jittered,
+    /// A regular grid rotated by atan(1/2), so that no two samples share a
+    /// row or column; this handles near-horizontal and near-vertical edges
+    /// better than an axis-aligned grid.
+    RotatedGrid
+}
+
+// Choose the rows and columns of a grid with exactly `count` cells, as close
+// to square as possible.
+fn grid_dimensions(count: usize) -> (usize, usize) {
+    let mut rows = (count as f32).sqrt() as usize;
+    while rows > 1 && !count.is_multiple_of(rows) {
+        rows -= 1;
+    }
+    let rows = rows.max(1);
+    (count / rows, rows)
+}
+
+impl SamplePattern {
+    /// Generate `count` sample positions as (x, y) offsets within the pixel,
+    /// each in the range [0.0, 1.0), where (0.0, 0.0) is the top left corner.
+    /// A single grid sample is always at the pixel center.
+    pub(crate) fn generate(&self, count: usize, rng: &mut Rng) ->
+            Vec<(f32, f32)> {
+        let (columns, rows) = grid_dimensions(count.max(1));
+        let cell_width = 1.0 / columns as f32;
+        let cell_height = 1.0 / rows as f32;
+
+        let mut samples = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = column as f32 * cell_width;
+                let y = row as f32 * cell_height;
+
+                let sample = match self {
+                    SamplePattern::Grid => {
+                        (x + 0.5 * cell_width, y + 0.5 * cell_height)
+                    },
+                    SamplePattern::Jittered => {
+                        (x + rng.next_f32() * cell_width,
+                            y + rng.next_f32() * cell_height)
+                    },
+                    SamplePattern::RotatedGrid => {
+                        // Rotate the cell center about the pixel center,
+                        // wrapping anything that lands outside the pixel
+                        // back around to the other side.
+                        let (sin, cos) = 0.5f32.atan().sin_cos();
+                        let cx = x + 0.5 * cell_width - 0.5;
+                        let cy = y + 0.5 * cell_height - 0.5;
+                        let rx = cx * cos - cy * sin + 0.5;
+                        let ry = cx * sin + cy * cos + 0.5;
+                        (rx - rx.floor(), ry - ry.floor())
+                    }
+                };
+                samples.push(sample);
+            }
+        }
+        samples
+    }
+}
@@ -1,26 +1,39 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use crate::vector_math::{Point, Vector};
 use crate::color::Color;
 use crate::render::{Surface, Primitive};
-use crate::shape::Shape;
+use crate::shape::{Shape, BoundingBox};
 use crate::space_partition::SpacePartition;
+use crate::mesh::{TriangleMesh, MeshShape};
 
 const MAX_DEPTH: u32 = 5;
 const MIN_CONTRIBUTION: f32 = 0.003;
 
+/// A point light.
 #[derive(Debug, Clone)]
 pub struct Light {
+    /// Where the light is
     pub position: Point,
+    /// Color and brightness of the light
     pub color: Color
 }
 
+/// The primitives and lights to render.
 pub struct Scene {
+    /// Color seen where rays don't hit anything
     pub background: Color,
     lights: Vec<Light>,
     primitives: Vec<Primitive>
 }
 
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene::new()
+    }
+}
+
 impl Scene {
+    /// Empty scene with a white background
     pub fn new() -> Scene {
         Scene {
             background: Color {r: 1.0, g: 1.0, b: 1.0},
@@ -29,37 +42,73 @@ impl Scene {
         }
     }
 
+    /// Add a shape drawn with the given surface.
     pub fn add_primitive(&mut self,
             shape: Box<dyn Shape>,
-            surface: Rc<dyn Surface>) {
+            surface: Arc<dyn Surface>) {
         self.primitives.push(
             Primitive {
-                shape: shape,
-                surface: surface
+                shape,
+                surface
             });
     }
 
+    /// Add `mesh` as a single primitive with the given surface. Its triangles
+    /// are sorted into a space partition of their own, rather than each
+    /// being a primitive.
+    pub fn add_mesh(&mut self, mesh: TriangleMesh, surface: Arc<dyn Surface>) {
+        self.add_primitive(Box::new(MeshShape::new(mesh)), surface);
+    }
+
+    /// Add a light.
     pub fn add_light(&mut self, light: Light) {
         self.lights.push(light);
     }
 
-    pub fn build_space_partition(&self) -> SpacePartition {
+    /// Box enclosing every bounded primitive in the scene, or None if there
+    /// aren't any. Unbounded primitives such as planes are left out.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.primitives.iter()
+            .filter_map(|primitive| primitive.shape.bounding_box())
+            .reduce(|total, bounding_box| total.expand_to_fit(&bounding_box))
+    }
+
+    /// Sort the primitives into a space partition for tracing.
+    pub fn build_space_partition(&self) -> SpacePartition<'_> {
         SpacePartition::from_primitives(&self.primitives)
     }
 
-    // Top-level tracing funtion. Given `ray` originating from the viewer at
-    // `src`, return the color of the point that it intersects in the scene.
-    //
-    // `space_partition` is an acceleration structure.
-    //
-    // `near` is the near-clipping distance; intersections closer to `src` will
-    // be ignored, meaning those parts of the scene will be invisible.
+    /// Top-level tracing function. Given `ray` originating from the viewer at
+    /// `src`, return the color of the point that it intersects in the scene.
+    ///
+    /// `space_partition` is an acceleration structure.
+    ///
+    /// `near` is the near-clipping distance; intersections closer to `src` will
+    /// be ignored, meaning those parts of the scene will be invisible. `far`
+    /// is the far-clipping distance, beyond which the same applies; pass
+    /// f32::INFINITY for no limit.
     pub fn trace(&self,
             space_partition: &SpacePartition,
             src: &Point,
             ray: &Vector,
-            near: f32) -> Color {
-        self.sub_trace(space_partition, src, ray, near, None, 1.0, 0)
+            near: f32,
+            far: f32) -> Color {
+        self.trace_coverage(space_partition, src, ray, near, far).0
+    }
+
+    /// Like `trace`, but also return the ray's coverage: 1.0 if it hit an
+    /// object in the scene, or 0.0 if it passed through to the background.
+    pub fn trace_coverage(&self,
+            space_partition: &SpacePartition,
+            src: &Point,
+            ray: &Vector,
+            near: f32,
+            far: f32) -> (Color, f32) {
+        match self.sub_trace(
+                space_partition, src, ray, near, far, None, 1.0, 0) {
+            Some(color) => (color, 1.0),
+            None => (self.background.clone(), 0.0)
+        }
     }
 
     // More detailed tracing function. Given `ray` originating from some point
@@ -67,7 +116,7 @@ impl Scene {
     // the point's visible color, based on the object the the ray intersects in
     // the scene and any subsequent reflections/refractions.
     //
-    // `near` is a near-clipping distance.
+    // `near` and `far` are near- and far-clipping distances.
     //
     // `ignore` is an optional primitive in the scene to ignore when calculating
     // intersections, typically the primitive on which `src` resides if the ray
@@ -79,15 +128,20 @@ impl Scene {
     //
     // `depth` is the recursion depth in terms of reflection/refraction rays.
     // Tracing will stop at a maximum threshold.
+    //
+    // Returns None if the ray doesn't intersect anything in the scene.
+    #[allow(clippy::too_many_arguments)]
     fn sub_trace(&self,
             space_partition: &SpacePartition,
             src: &Point,
             ray: &Vector,
             near: f32,
+            far: f32,
             ignore: Option<&dyn Shape>,
             contribution: f32,
-            depth: u32) -> Color {
-        let intersection = space_partition.intersect(src, ray, near, ignore);
+            depth: u32) -> Option<Color> {
+        let intersection =
+            space_partition.intersect(src, ray, near, far, ignore);
 
         if let Some((normal, distance, primitive)) = intersection {
             let shape = primitive.shape.as_ref();
@@ -96,6 +150,17 @@ impl Scene {
             let back_face = normal.dot(ray) > 0.0;
             let mut total_color = Color::black();
 
+            // Rays leaving the surface ignore the shape they leave from,
+            // unless it has other parts they could hit. Those rays start a
+            // short distance away instead, as for refraction below but a
+            // little further, since taking rays in and out of instances
+            // adds to the rounding error.
+            let (leaving_near, leaving_ignore) = if shape.is_composite() {
+                (0.001, None)
+            } else {
+                (0.0, Some(shape))
+            };
+
             // Surfaces are one-sided and invisible if viewed from the back.
             // However, refracted rays will still hit back faces, so we can't
             // ignore them completely.
@@ -105,16 +170,14 @@ impl Scene {
                     let light_distance = surface_to_light.magnitude();
                     let light_direction = surface_to_light / light_distance;
         
-                    let light_blocked = match space_partition.intersect(
-                            &surface_position,
-                            &light_direction,
-                            0.0,
-                            Some(shape)) {
-                        Some((_, blocker_distance, _)) => {
-                            blocker_distance <= light_distance
-                        },
-                        None => false
-                    };
+                    // Only objects between the surface and the light can
+                    // block it, so the search stops at the light.
+                    let light_blocked = space_partition.intersect(
+                        &surface_position,
+                        &light_direction,
+                        leaving_near,
+                        light_distance,
+                        leaving_ignore).is_some();
         
                     if !light_blocked {
                         let direct_color = surface.get_visible_color(
@@ -133,10 +196,12 @@ impl Scene {
                             space_partition,
                             &surface_position,
                             &reflected_ray,
-                            0.0,
-                            Some(shape),
+                            leaving_near,
+                            f32::INFINITY,
+                            leaving_ignore,
                             reflection_contribution,
-                            depth + 1);
+                            depth + 1)
+                            .unwrap_or_else(|| self.background.clone());
     
                         total_color += reflected_color *
                             primitive.surface.get_reflectance();
@@ -184,18 +249,22 @@ impl Scene {
                         &surface_position,
                         &refracted_ray,
                         0.0001,
+                        f32::INFINITY,
                         None,
                         refraction_contribution,
-                        depth + 1);
+                        depth + 1)
+                        .unwrap_or_else(|| self.background.clone());
 
                         total_color += refracted_color * transmittance;
                 }
             }
 
-            total_color.clamp();
-            return total_color;
+            // The color is left unclamped here, so the render keeps its full
+            // dynamic range; it's up to the output encoder to clamp it if
+            // necessary.
+            return Some(total_color);
         }
     
-        self.background.clone()
+        None
     }
 }
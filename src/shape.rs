@@ -1,24 +1,73 @@
-use crate::vector_math::{Point, Vector, Axis};
+use crate::vector_math::{Axis, Point, Vector, Transform};
 
+/// Where a ray hits a shape.
+#[derive(Debug, Clone)]
 pub struct IntersectResult {
+    /// Surface normal at the hit, facing out of the shape
     pub normal: Vector,
+    /// Distance along the ray to the hit, in multiples of the ray's length
     pub dist: f32
 }
 
+/// A stretch of a ray that's inside a solid shape, from where the ray enters
+/// its surface to where it leaves again. The ends of unbounded solids like
+/// half-spaces can be infinitely far away, with zero normals.
+#[derive(Debug, Clone)]
+pub struct Interval {
+    /// Where the ray goes into the solid
+    pub enter: IntersectResult,
+    /// Where the ray comes out of it
+    pub exit: IntersectResult
+}
+
+/// A box aligned with the world axes.
 #[derive(Debug, Clone)]
 pub struct BoundingBox {
+    /// Corner with the smallest coordinates
     pub corner: Point,
+    /// Size of the box along each axis
     pub extent: Vector
 }
 
-pub trait Shape {
+/// Geometry that rays can be traced against.
+pub trait Shape: Send + Sync {
+    /// Nearest point where the ray from `src` along `ray` hits the surface,
+    /// at least `near` along the ray
     fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
             Option<IntersectResult>;
 
-    fn bounding_box(&self) -> BoundingBox;
+    /// Box enclosing the shape, or None if it's unbounded (e.g. an infinite
+    /// plane).
+    fn bounding_box(&self) -> Option<BoundingBox>;
+
+    /// Whether the shape is made up of parts that can shadow or reflect one
+    /// another, like a group. Rays leaving a shape's surface normally ignore
+    /// that shape, so rounding errors can't make them hit it again at the
+    /// same point, but that would hide the other parts of these.
+    fn is_composite(&self) -> bool {
+        false
+    }
+
+    /// For shapes that enclose a solid, every stretch of the whole line
+    /// through `src` along `ray` that's inside it, behind `src` as well as in
+    /// front, in order along the ray. This is what lets solids be combined
+    /// with CSG. Shapes that are only surfaces, like polygons or open tubes,
+    /// return None.
+    fn intervals(&self, _src: &Point, _ray: &Vector) -> Option<Vec<Interval>> {
+        None
+    }
+
+    /// Normal of the shape's actual surface at `hit`, facing the same side
+    /// as the hit's normal. Shapes that shade with normals interpolated
+    /// from their corners, like polygons and mesh triangles, give the normal
+    /// of their plane, which is what says which way a ray crosses them.
+    fn face_normal(&self, hit: &IntersectResult) -> Vector {
+        hit.normal.clone()
+    }
 }
 
 impl BoundingBox {
+    /// Empty box at the origin
     pub fn zero() -> BoundingBox {
         BoundingBox {
             corner: Point::origin(),
@@ -30,10 +79,12 @@ impl BoundingBox {
         }
     }
 
+    /// Corner with the smallest coordinates
     pub fn min_corner(&self) -> &Point {
         &self.corner
     }
 
+    /// Corner with the largest coordinates
     pub fn max_corner(&self) -> Point {
         &self.corner + &self.extent
     }
@@ -42,6 +93,7 @@ impl BoundingBox {
     //     self.extent.dx * self.extent.dy * self.extent.dz
     // }
 
+    /// Total area of the box's six faces
     pub fn surface_area(&self) -> f32 {
         self.extent.dx * self.extent.dy * 2.0 +
         self.extent.dy * self.extent.dz * 2.0 +
@@ -56,6 +108,7 @@ impl BoundingBox {
     //     }
     // }
 
+    /// Smallest box enclosing both this box and `other`
     pub fn expand_to_fit(&self, other: &BoundingBox) -> BoundingBox {
         let min_x = self.corner.x.min(other.corner.x);
         let min_y = self.corner.y.min(other.corner.y);
@@ -81,7 +134,59 @@ impl BoundingBox {
         }
     }
 
-    pub fn intersect(&self, src: &Point, ray: &Vector, near_cull: f32) -> bool {
+    /// Box around the space that's in both this box and `other`, which is
+    /// empty but still placed between them if they don't meet.
+    pub fn overlap(&self, other: &BoundingBox) -> BoundingBox {
+        let (high, other_high) = (self.max_corner(), other.max_corner());
+        let corner = Point {
+            x: self.corner.x.max(other.corner.x),
+            y: self.corner.y.max(other.corner.y),
+            z: self.corner.z.max(other.corner.z)
+        };
+        let extent = Vector {
+            dx: (high.x.min(other_high.x) - corner.x).max(0.0),
+            dy: (high.y.min(other_high.y) - corner.y).max(0.0),
+            dz: (high.z.min(other_high.z) - corner.z).max(0.0)
+        };
+        BoundingBox {corner, extent}
+    }
+
+    /// Box enclosing this box after it's been through `transform`. Each edge
+    /// of the box reaches out along each world axis by the size of its
+    /// transformed component on that axis.
+    pub fn transformed(&self, transform: &Transform) -> BoundingBox {
+        let half = &self.extent * 0.5;
+        let center = transform.apply_point(&(&self.corner + &half));
+        let edges = [
+            transform.apply_vector(&Vector {dx: half.dx, dy: 0.0, dz: 0.0}),
+            transform.apply_vector(&Vector {dx: 0.0, dy: half.dy, dz: 0.0}),
+            transform.apply_vector(&Vector {dx: 0.0, dy: 0.0, dz: half.dz})];
+        let reach = |axis: Axis| edges.iter()
+            .map(|edge| edge.component(axis).abs())
+            .sum::<f32>();
+        let reach = Vector {
+            dx: reach(Axis::X),
+            dy: reach(Axis::Y),
+            dz: reach(Axis::Z)
+        };
+
+        BoundingBox {
+            corner: Point {
+                x: center.x - reach.dx,
+                y: center.y - reach.dy,
+                z: center.z - reach.dz
+            },
+            extent: reach * 2.0
+        }
+    }
+
+    /// Check whether the ray from `src` passes through the box anywhere
+    /// between the distances `near_cull` and `far_cull` along it.
+    pub fn intersect(&self,
+            src: &Point,
+            ray: &Vector,
+            near_cull: f32,
+            far_cull: f32) -> bool {
         // TO DO: does rust support branchless min/max? This could be made
         // faster by eliminating the if statements.
 
@@ -90,8 +195,8 @@ impl BoundingBox {
         // far plane.  If the first of the far plane intersections comes before
         // the last near plane intersection, the ray misses the box.
 
-        let mut largest_near = std::f32::MIN;
-        let mut smallest_far = std::f32::MAX;
+        let mut largest_near = f32::MIN;
+        let mut smallest_far = f32::MAX;
 
         let src_to_min_corner = &self.corner - src;
         let src_to_max_corner = &self.corner + &self.extent - src;
@@ -168,6 +273,7 @@ impl BoundingBox {
 
         // Note the >= comparison - this has to cover the case of 0-width boxes
         // around flat polygons.
-        return smallest_far >= largest_near && smallest_far >= near_cull;
+        smallest_far >= largest_near && smallest_far >= near_cull &&
+            largest_near <= far_cull
     }
 }
@@ -1,25 +1,59 @@
-use std::ops::Deref;
+use std::sync::Arc;
 use crate::vector_math::{Axis, Point, Vector};
 use crate::shape::Shape;
 use crate::shape::BoundingBox;
 use crate::render::Primitive;
 
 
-struct InteriorNode<'a> {
-    over: Box<SpacePartition<'a>>,
-    under: Box<SpacePartition<'a>>,
+// Something that can be stored in a partition tree: anything with a shape.
+// Items are cloned into every leaf they overlap, so they should be cheap to
+// clone, like references or shared pointers.
+pub(crate) trait PartitionItem: Clone {
+    fn shape(&self) -> &dyn Shape;
+}
+
+impl PartitionItem for &Primitive {
+    fn shape(&self) -> &dyn Shape {
+        self.shape.as_ref()
+    }
+}
+
+impl PartitionItem for Arc<dyn Shape> {
+    fn shape(&self) -> &dyn Shape {
+        self.as_ref()
+    }
+}
+
+struct InteriorNode<T> {
+    over: Box<PartitionNode<T>>,
+    under: Box<PartitionNode<T>>,
     axis: Axis,
     plane: f32
 }
 
-enum ChildNode<'a> {
-    Leaf(Vec<&'a Primitive>),
-    Interior(InteriorNode<'a>)
+enum ChildNode<T> {
+    Leaf(Vec<T>),
+    Interior(InteriorNode<T>)
 }
 
-pub struct SpacePartition<'a> {
+struct PartitionNode<T> {
     bounding_box: BoundingBox,
-    child: ChildNode<'a>
+    child: ChildNode<T>
+}
+
+// A k-d tree of items with shapes, used both for the primitives in a scene
+// and for the shapes in a group.
+pub(crate) struct PartitionTree<T> {
+    root: PartitionNode<T>,
+    // Items with no bounding box, such as planes, which can't be placed in
+    // the tree and are tested against every ray instead
+    unbounded: Vec<T>
+}
+
+/// The primitives of a scene, sorted by where they are so a ray only has to
+/// be tested against the ones near its path.
+pub struct SpacePartition<'a> {
+    tree: PartitionTree<&'a Primitive>
 }
 
 struct SplitAppraisal {
@@ -35,9 +69,9 @@ struct SplitDecision {
 }
 
 #[derive(Clone)]
-struct BoxedPrimitive<'a> (&'a Primitive, BoundingBox);
+struct BoxedPrimitive<T> (T, BoundingBox);
 
-fn find_splitting_plane(primitives: &[BoxedPrimitive],
+fn find_splitting_plane<T>(primitives: &[BoxedPrimitive<T>],
         axis: Axis,
         no_split_cost: f32) -> Option<SplitDecision> {
 
@@ -45,7 +79,7 @@ fn find_splitting_plane(primitives: &[BoxedPrimitive],
         return None;
     }
 
-    println!("Partitioning {} primitives on {:?}", primitives.len(), axis);
+    let sweep = SplitSweep::new(primitives, axis);
 
     let mut min_cost = no_split_cost;
     let mut best_plane = 0.0;
@@ -54,7 +88,7 @@ fn find_splitting_plane(primitives: &[BoxedPrimitive],
 
     for BoxedPrimitive(_, bounding_box) in primitives {
         let plane = bounding_box.min_corner().component(axis);
-        let appraisal = appraise_split(primitives, axis, plane);
+        let appraisal = sweep.appraise_split(plane);
 
         if appraisal.cost < min_cost {
             best_under_box = appraisal.under_box;
@@ -64,7 +98,7 @@ fn find_splitting_plane(primitives: &[BoxedPrimitive],
         }
 
         let plane = bounding_box.max_corner().component(axis);
-        let appraisal = appraise_split(primitives, axis, plane);
+        let appraisal = sweep.appraise_split(plane);
 
         if appraisal.cost < min_cost {
             best_under_box = appraisal.under_box;
@@ -76,14 +110,8 @@ fn find_splitting_plane(primitives: &[BoxedPrimitive],
 
     // Don't do any split that would put all the primitives on one side.
     // We have to have both an under and over box.
-    let best_over_box = match best_over_box {
-        None => return None,
-        Some(bounding_box) => bounding_box
-    };
-    let best_under_box = match best_under_box {
-        None => return None,
-        Some(bounding_box) => bounding_box
-    };
+    let best_over_box = best_over_box?;
+    let best_under_box = best_under_box?;
 
     if min_cost < no_split_cost {
         Some(SplitDecision {
@@ -96,66 +124,113 @@ fn find_splitting_plane(primitives: &[BoxedPrimitive],
     }
 }
 
-// Determine the cost of splitting a set of primitives on a given plane
-fn appraise_split(
-    primitives: &[BoxedPrimitive],
-    axis: Axis,
-    plane: f32) -> SplitAppraisal {
-
-    let mut under_count = 0;
-    let mut over_count = 0;
-    let mut under_box: Option<BoundingBox> = None;
-    let mut over_box: Option<BoundingBox> = None;
+// The primitives' bounding boxes sorted by where they start and end along
+// the splitting axis. The primitives on either side of any plane are then
+// a run of one of the sorted lists, which can be found with a binary search
+// rather than by checking every primitive; this keeps building the
+// partition for large meshes from taking quadratic time.
+struct SplitSweep {
+    // Near side of each box on the axis, in ascending order
+    mins: Vec<f32>,
+    // Boxes enclosing the first 1, 2, 3... boxes in order of their near
+    // sides
+    under_boxes: Vec<BoundingBox>,
+    // Far side of each box on the axis, in ascending order
+    maxes: Vec<f32>,
+    // Boxes enclosing the boxes from each index to the end, in order of
+    // their far sides
+    over_boxes: Vec<BoundingBox>
+}
 
-    for BoxedPrimitive(_, bounding_box) in primitives {
-        if bounding_box.min_corner().component(axis) < plane {
-            under_count += 1;
-            let new_under_box = match under_box {
+impl SplitSweep {
+    fn new<T>(primitives: &[BoxedPrimitive<T>], axis: Axis) -> SplitSweep {
+        let mut by_min: Vec<&BoundingBox> =
+            primitives.iter().map(|BoxedPrimitive(_, b)| b).collect();
+        by_min.sort_by(|a, b| a.min_corner().component(axis)
+            .total_cmp(&b.min_corner().component(axis)));
+
+        let mut by_max = by_min.clone();
+        by_max.sort_by(|a, b| a.max_corner().component(axis)
+            .total_cmp(&b.max_corner().component(axis)));
+
+        let mut under_boxes: Vec<BoundingBox> =
+            Vec::with_capacity(by_min.len());
+        for bounding_box in &by_min {
+            let under_box = match under_boxes.last() {
                 Some(under_box) => under_box.expand_to_fit(bounding_box),
-                None => bounding_box.clone()
+                None => (*bounding_box).clone()
             };
-            under_box = Some(new_under_box);
+            under_boxes.push(under_box);
         }
 
-        if bounding_box.max_corner().component(axis) >= plane {
-            over_count += 1;
-            let new_over_box = match over_box {
+        let mut over_boxes: Vec<BoundingBox> =
+            Vec::with_capacity(by_max.len());
+        for bounding_box in by_max.iter().rev() {
+            let over_box = match over_boxes.last() {
                 Some(over_box) => over_box.expand_to_fit(bounding_box),
-                None => bounding_box.clone()
+                None => (*bounding_box).clone()
             };
-            over_box = Some(new_over_box);
+            over_boxes.push(over_box);
+        }
+        over_boxes.reverse();
+
+        SplitSweep {
+            mins: by_min.iter().map(|b| b.min_corner().component(axis))
+                .collect(),
+            under_boxes,
+            maxes: by_max.iter().map(|b| b.max_corner().component(axis))
+                .collect(),
+            over_boxes
         }
     }
 
-    let cost = match under_box.as_ref() {
-        Some(under_box) => match over_box.as_ref() {
-            Some(over_box) => {
-                appraise(under_count, &under_box) +
-                appraise(over_count, &over_box)
+    // Determine the cost of splitting the primitives on a given plane
+    fn appraise_split(&self, plane: f32) -> SplitAppraisal {
+        // Primitives starting before the plane are under it, and those
+        // ending at or after it are over it.
+        let under_count = self.mins.partition_point(|&min| min < plane);
+        let first_over = self.maxes.partition_point(|&max| max < plane);
+        let over_count = self.maxes.len() - first_over;
+
+        let under_box = match under_count {
+            0 => None,
+            _ => Some(self.under_boxes[under_count - 1].clone())
+        };
+        let over_box = match over_count {
+            0 => None,
+            _ => Some(self.over_boxes[first_over].clone())
+        };
+
+        let cost = match under_box.as_ref() {
+            Some(under_box) => match over_box.as_ref() {
+                Some(over_box) => {
+                    appraise(under_count, under_box) +
+                    appraise(over_count, over_box)
+                },
+                None => appraise(under_count, under_box)
             },
-            None => appraise(under_count, &under_box)
-        },
-        None => match over_box.as_ref() {
-            Some(over_box) => appraise(over_count, &over_box),
-            None => 0.0
-        }
-    };
+            None => match over_box.as_ref() {
+                Some(over_box) => appraise(over_count, over_box),
+                None => 0.0
+            }
+        };
 
-    SplitAppraisal {
-        over_box: over_box,
-        under_box: under_box,
-        cost: cost
+        SplitAppraisal {
+            over_box,
+            under_box,
+            cost
+        }
     }
 }
 
-fn split<'a>(
-    boxed_primitives: &[BoxedPrimitive<'a>],
+fn split<T: Clone>(
+    boxed_primitives: &[BoxedPrimitive<T>],
     axis: Axis,
     plane: f32) ->
-        (Vec<BoxedPrimitive<'a>>, Vec<BoxedPrimitive<'a>>) {
+        (Vec<BoxedPrimitive<T>>, Vec<BoxedPrimitive<T>>) {
 
-    let mut over: Vec<BoxedPrimitive<'a>> = vec![];
-    let mut under: Vec<BoxedPrimitive<'a>> = vec![];
+    let mut over: Vec<BoxedPrimitive<T>> = vec![];
+    let mut under: Vec<BoxedPrimitive<T>> = vec![];
 
     for boxed_primitive in boxed_primitives {
         let BoxedPrimitive(_, bounding_box) = boxed_primitive;
@@ -194,45 +269,169 @@ fn appraise(primitive_count: usize, bounding_box: &BoundingBox) -> f32 {
 
 impl<'a> SpacePartition<'a> {
 
+    /// Partition `primitives`.
     pub fn from_primitives(
             primitives: &'a[Primitive]) -> SpacePartition<'a> {
+        SpacePartition {
+            tree: PartitionTree::from_items(primitives.iter())
+        }
+    }
+
+    /// Given `ray` originating from `src`, find the primitive in the scene
+    /// that the ray intersects. If an intersection is found, then return a
+    /// tuple of: the surface normal at the intersection point, the distance to
+    /// the intersection point, and the primitive that was intersected.
+    ///
+    /// `near` and `far` are near- and far-clipping distances; intersections
+    /// outside that range are ignored.
+    ///
+    /// `ignore` is a primitive to ignore when calculating intersections.
+    pub fn intersect(&self,
+        src: &Point,
+        ray: &Vector,
+        near: f32,
+        far: f32,
+        ignore: Option<&dyn Shape>) ->
+            Option<(Vector, f32, &Primitive)> {
+        self.tree.intersect(src, ray, near, far, ignore)
+            .map(|(normal, distance, primitive)| (normal, distance, *primitive))
+    }
+}
+
+impl<T: PartitionItem> PartitionTree<T> {
+    pub(crate) fn from_items(
+            items: impl Iterator<Item = T>) -> PartitionTree<T> {
+
+        let mut boxed_primitives: Vec<BoxedPrimitive<T>> = vec![];
+        let mut unbounded: Vec<T> = vec![];
+        let mut total_box: Option<BoundingBox> = None;
+
+        for item in items {
+            match item.shape().bounding_box() {
+                Some(bounding_box) => {
+                    total_box = Some(match total_box {
+                        Some(total_box) =>
+                            total_box.expand_to_fit(&bounding_box),
+                        None => bounding_box.clone()
+                    });
+                    boxed_primitives.push(
+                        BoxedPrimitive(item, bounding_box));
+                },
+                None => {
+                    unbounded.push(item);
+                }
+            }
+        }
 
-        if primitives.is_empty() {
-            SpacePartition {
+        let root = match total_box {
+            Some(total_box) => PartitionNode::from_boxed_primitives(
+                &boxed_primitives, Axis::X, total_box),
+            None => PartitionNode {
                 bounding_box: BoundingBox::zero(),
                 child: ChildNode::Leaf(vec![])
             }
+        };
+
+        PartitionTree {
+            root,
+            unbounded
+        }
+    }
+
+    // Box enclosing every item in the tree, or None if any of them are
+    // unbounded.
+    pub(crate) fn bounding_box(&self) -> Option<BoundingBox> {
+        if self.unbounded.is_empty() {
+            Some(self.root.bounding_box.clone())
         } else {
-            let first_box = primitives[0].shape.bounding_box();
+            None
+        }
+    }
 
-            let mut boxed_primitives =
-                vec![BoxedPrimitive(&primitives[0], first_box.clone())];
-            let mut total_box = first_box;
+    // Every item that might meet the line through `src` along `ray`, both
+    // in front of `src` and behind it, each listed once.
+    pub(crate) fn items_along(&self, src: &Point, ray: &Vector) -> Vec<&T> {
+        let mut items: Vec<&T> = vec![];
+        self.root.items_along(src, ray, &mut items);
 
-            for primitive in &primitives[1..] {
-                let bounding_box = primitive.shape.bounding_box();
-                total_box = total_box.expand_to_fit(&bounding_box);
-                boxed_primitives.push(BoxedPrimitive(&primitive, bounding_box));
-            }
+        // Items that straddle a split are in the leaves on both sides.
+        let address = |item: &&T| item.shape() as *const dyn Shape as *const ()
+            as usize;
+        items.sort_by_key(address);
+        items.dedup_by_key(|item| address(item));
+
+        items.extend(self.unbounded.iter());
+        items
+    }
+
+    // Find the nearest item that the ray intersects, as for
+    // `SpacePartition::intersect`.
+    pub(crate) fn intersect(&self,
+        src: &Point,
+        ray: &Vector,
+        near: f32,
+        far: f32,
+        ignore: Option<&dyn Shape>) ->
+            Option<(Vector, f32, &T)> {
 
-            SpacePartition::from_boxed_primitives(
-                &boxed_primitives, Axis::X, total_box)
+        let bounded_result = self.root.intersect(src, ray, near, far, ignore);
+        if self.unbounded.is_empty() {
+            return bounded_result;
+        }
+
+        // Only unbounded primitives nearer than anything in the tree matter.
+        let unbounded_far = match &bounded_result {
+            Some((_, distance, _)) => *distance,
+            None => far
+        };
+        let unbounded_result = intersect_primitives(&self.unbounded,
+            src, ray, near, unbounded_far, ignore);
+
+        match (bounded_result, unbounded_result) {
+            (Some(bounded), Some(unbounded)) => {
+                if unbounded.1 < bounded.1 {
+                    Some(unbounded)
+                } else {
+                    Some(bounded)
+                }
+            },
+            (bounded, unbounded) => bounded.or(unbounded)
+        }
+    }
+}
+
+impl<T: PartitionItem> PartitionNode<T> {
+    // Add every item in this part of the tree whose leaf the whole line
+    // through `src` along `ray` passes through to `items`.
+    fn items_along<'a>(&'a self, src: &Point, ray: &Vector,
+            items: &mut Vec<&'a T>) {
+        if !self.bounding_box.intersect(src, ray, f32::NEG_INFINITY,
+                f32::INFINITY) {
+            return;
+        }
+
+        match &self.child {
+            ChildNode::Leaf(primitives) => items.extend(primitives.iter()),
+            ChildNode::Interior(node) => {
+                node.under.items_along(src, ray, items);
+                node.over.items_along(src, ray, items);
+            }
         }
     }
 
     fn from_boxed_primitives(
-            boxed_primitives: &[BoxedPrimitive<'a>],
+            boxed_primitives: &[BoxedPrimitive<T>],
             axis: Axis,
-            bounding_box: BoundingBox) -> SpacePartition<'a> {
+            bounding_box: BoundingBox) -> PartitionNode<T> {
 
         let no_split_cost = appraise(boxed_primitives.len(), &bounding_box);
-        let decision = find_splitting_plane(&boxed_primitives, axis, no_split_cost);
+        let decision = find_splitting_plane(boxed_primitives, axis, no_split_cost);
         match decision {
             None => {
-                let primitives: Vec<_> = boxed_primitives.into_iter().map(
-                    |BoxedPrimitive(primitive, _)| *primitive).collect();
-                SpacePartition {
-                    bounding_box: bounding_box,
+                let primitives: Vec<_> = boxed_primitives.iter().map(
+                    |BoxedPrimitive(primitive, _)| primitive.clone()).collect();
+                PartitionNode {
+                    bounding_box,
                     child: ChildNode::Leaf(primitives)
                 }
             },
@@ -240,69 +439,66 @@ impl<'a> SpacePartition<'a> {
                 let (over, under) = split(boxed_primitives, axis, plane);
                 let next_axis = advance(axis);
 
-                let over = Box::new(SpacePartition::from_boxed_primitives(
+                let over = Box::new(PartitionNode::from_boxed_primitives(
                     &over, next_axis, over_box));
-                let under = Box::new(SpacePartition::from_boxed_primitives(
+                let under = Box::new(PartitionNode::from_boxed_primitives(
                     &under, next_axis, under_box));
 
-                SpacePartition {
-                    bounding_box: bounding_box,
+                PartitionNode {
+                    bounding_box,
                     child: ChildNode::Interior(InteriorNode {
-                        over: over,
-                        under: under,
-                        axis: axis,
-                        plane: plane
+                        over,
+                        under,
+                        axis,
+                        plane
                 })}
             }
         }
     }
 
-    // Given `ray` originating from `src`, find the primitive in the scene
-    // that the ray intersects. If an intersection is found, the return a
-    // tuple of: the surface normal at the intersection point, the distance to
-    // the intersection point, and the primitive that was intersected.
-    //
-    // `near` is a near-clipping distance.
-    //
-    // `ignore` is a primitive to ignore when calculating intersections.
-    pub fn intersect(&self,
+    // Find the nearest primitive in this part of the tree that the ray
+    // intersects, as for `SpacePartition::intersect`.
+    fn intersect(&self,
         src: &Point,
         ray: &Vector,
         near: f32,
+        far: f32,
         ignore: Option<&dyn Shape>) ->
-            Option<(Vector, f32, &Primitive)> {
+            Option<(Vector, f32, &T)> {
 
         // Quick test - does the ray hit the bounding box for this partition?
-        if !self.bounding_box.intersect(src, ray, near) {
+        if !self.bounding_box.intersect(src, ray, near, far) {
             // No intersection possible if the ray missed the bounding box.
             return None;
         }
         
         match &self.child {
             ChildNode::Leaf(primitives) => {
-                intersect_primitives(primitives, src, ray, near, ignore)
+                intersect_primitives(primitives, src, ray, near, far, ignore)
             },
             ChildNode::Interior(node) => {
-                node.intersect(src, ray, near, ignore)
+                node.intersect(src, ray, near, far, ignore)
             }
         }
     }
 }
 
-impl<'a> InteriorNode<'a> {
+impl<T: PartitionItem> InteriorNode<T> {
     fn intersect(&self,
         src: &Point,
         ray: &Vector,
         near: f32,
+        far: f32,
         ignore: Option<&dyn Shape>) ->
-            Option<(Vector, f32, &Primitive)> {
+            Option<(Vector, f32, &T)> {
 
         // Intersect whichever sub-partition the ray starts in first, then
         // hopefully skip the other one.
 
         if src.component(self.axis) < self.plane {
             // Starting on the under side of the plane.
-            let under_result = self.under.intersect(src, ray, near, ignore);
+            let under_result =
+                self.under.intersect(src, ray, near, far, ignore);
 
             // Need to check the other side in two cases:
             // - If the ray didn't hit anything, obviously.
@@ -319,14 +515,14 @@ impl<'a> InteriorNode<'a> {
             };
 
             if check_over {
-                self.over.intersect(src, ray, near, ignore)
+                self.over.intersect(src, ray, near, far, ignore)
             } else {
                 under_result
             }
         } else {
             // Starting on the over side of the plane.
             let over_result =
-                self.over.intersect(src, ray, near, ignore);
+                self.over.intersect(src, ray, near, far, ignore);
 
             let check_under = match over_result {
                 None => true,
@@ -337,7 +533,7 @@ impl<'a> InteriorNode<'a> {
             };
 
             if check_under {
-                self.under.intersect(src, ray, near, ignore)
+                self.under.intersect(src, ray, near, far, ignore)
             } else {
                 over_result
             }
@@ -345,33 +541,33 @@ impl<'a> InteriorNode<'a> {
     }
 }
 
-fn intersect_primitives<'a>(
-    primitives: &Vec<&'a Primitive>,
+fn intersect_primitives<'a, T: PartitionItem>(
+    primitives: &'a [T],
     src: &Point,
     ray: &Vector,
     near: f32,
+    far: f32,
     ignore: Option<&dyn Shape>) ->
-        Option<(Vector, f32, &'a Primitive)> {
+        Option<(Vector, f32, &'a T)> {
 
     // Test all the prmitives using a linear search and return the nearest
     // intersection.
-    let mut best_result: Option<(Vector, f32, &Primitive)> = None;
+    let mut best_result: Option<(Vector, f32, &T)> = None;
 
     for primitive in primitives {
         if let Some(ignored_shape) = ignore {
-            if ignored_shape as *const _ ==
-                    primitive.shape.deref() as *const _ {
+            if std::ptr::addr_eq(ignored_shape, primitive.shape()) {
                 continue;
             }
         }
 
         if let Some(intersection) =
-                primitive.shape.intersect(src, ray, near) {
+                primitive.shape().intersect(src, ray, near) {
             let better_result_found = match &best_result {
                 Some((_, prior_nearest, _)) =>
                     intersection.dist < *prior_nearest,
                 None =>
-                    true
+                    intersection.dist <= far
             };
 
             if better_result_found {
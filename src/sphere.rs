@@ -1,16 +1,20 @@
 use crate::vector_math;
 use crate::vector_math::{Point, Vector};
-use crate::shape::{Shape, IntersectResult, BoundingBox};
+use crate::shape::{Shape, IntersectResult, BoundingBox, Interval};
+use crate::csg;
 
 
+/// A sphere of `radius` around `center`.
 pub struct Sphere {
+    /// Center of the sphere
     pub center: Point,
+    /// Radius of the sphere
     pub radius: f32
 }
 
 impl Shape for Sphere {
-    fn bounding_box(&self) -> BoundingBox {
-        BoundingBox {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(BoundingBox {
             corner: Point {
                 x: self.center.x - self.radius,
                 y: self.center.y - self.radius,
@@ -21,7 +25,11 @@ impl Shape for Sphere {
                 dy: self.radius * 2.0,
                 dz: self.radius * 2.0
             }
-        }
+        })
+    }
+
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        Some(csg::convex_intervals(self, src, ray))
     }
 
     fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
@@ -69,7 +77,7 @@ impl Shape for Sphere {
                 // intersection point from the center.
                 let normal = (src + t * ray - &self.center).normalized();
                 return Some(IntersectResult {
-                    normal: normal,
+                    normal,
                     dist: t
                 });
             }
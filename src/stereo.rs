@@ -0,0 +1,127 @@
+use crate::vector_math;
+use crate::color::Color;
+use crate::render;
+use crate::render::{View, RenderOptions, RenderTarget, Progress};
+use crate::scene::Scene;
+
+/// Ways of combining the left and right eye images into one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye on the left, right eye on the right, in an image twice as
+    /// wide as the view
+    SideBySide,
+    /// Left eye on top, right eye below, in an image twice as high as the
+    /// view
+    OverUnder,
+    /// Red-cyan anaglyph: the red channel comes from the left eye and the
+    /// green and blue channels from the right
+    Anaglyph
+}
+
+/// Settings for rendering a view as a stereo pair. The eyes are placed
+/// either side of the view's `from` point and turned inwards (toed in) so
+/// their lines of sight cross at the convergence distance; objects at that
+/// distance appear at the depth of the screen.
+#[derive(Debug, Clone)]
+pub struct Stereo {
+    /// How the two eyes' images are combined
+    pub layout: StereoLayout,
+    /// Distance between the eyes. If unset, it's 1/30 of the convergence
+    /// distance, a common rule of thumb for comfortable viewing.
+    pub eye_separation: Option<f32>,
+    /// Distance from the viewer to where the eyes' lines of sight cross. If
+    /// unset, it's the distance to `at`.
+    pub convergence: Option<f32>
+}
+
+impl Stereo {
+    /// Views for the left and right eyes.
+    pub fn eye_views(&self, view: &View) -> (View, View) {
+        let to_at = &view.at - &view.from;
+        let convergence = self.convergence.unwrap_or_else(|| to_at.magnitude());
+        let separation = self.eye_separation.unwrap_or(convergence / 30.0);
+
+        let forward = to_at.normalized();
+        let right =
+            vector_math::cross(&forward, &view.rolled_up()).normalized();
+        let converge_at = &view.from + &forward * convergence;
+
+        let eye_view = |offset: f32| {
+            let mut eye = view.clone();
+            eye.from = &view.from + &right * offset;
+            eye.at = converge_at.clone();
+            eye
+        };
+        (eye_view(-separation / 2.0), eye_view(separation / 2.0))
+    }
+
+    /// Width and height of the combined image for `view`, in pixels.
+    pub fn image_size(&self, view: &View) -> (usize, usize) {
+        let (width, height) = (view.width as usize, view.height as usize);
+        match self.layout {
+            StereoLayout::SideBySide => (width * 2, height),
+            StereoLayout::OverUnder => (width, height * 2),
+            StereoLayout::Anaglyph => (width, height)
+        }
+    }
+
+    /// Render the scene from each eye and combine the images into `target`,
+    /// which must be the size given by `image_size`.
+    pub fn render(&self,
+            view: &View,
+            scene: &Scene,
+            target: &mut RenderTarget,
+            options: &RenderOptions) {
+        let (left_view, right_view) = self.eye_views(view);
+        let (width, height) = (view.width as usize, view.height as usize);
+
+        let mut left = RenderTarget::new(width, height);
+        let mut right = RenderTarget::new(width, height);
+        options.report(Progress::LeftEye);
+        render::render(&left_view, scene, &mut left, options);
+        options.report(Progress::RightEye);
+        render::render(&right_view, scene, &mut right, options);
+
+        match self.layout {
+            StereoLayout::SideBySide => {
+                copy_image(&left, target, 0, 0);
+                copy_image(&right, target, width, 0);
+            },
+            StereoLayout::OverUnder => {
+                copy_image(&left, target, 0, 0);
+                copy_image(&right, target, 0, height);
+            },
+            StereoLayout::Anaglyph => {
+                for j in 0..height {
+                    for i in 0..width {
+                        let color = Color {
+                            r: left.get(i, j).r,
+                            g: right.get(i, j).g,
+                            b: right.get(i, j).b
+                        };
+                        let alpha =
+                            left.get_alpha(i, j).max(right.get_alpha(i, j));
+                        target.set_with_alpha(i, j, color, alpha);
+                        target.set_sample_count(i, j,
+                            left.get_sample_count(i, j) +
+                                right.get_sample_count(i, j));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Copy every pixel of `source` into `target`, with the top left corner of
+// `source` placed at (`x`, `y`).
+fn copy_image(source: &RenderTarget, target: &mut RenderTarget,
+        x: usize, y: usize) {
+    for j in 0..source.height {
+        for i in 0..source.width {
+            target.set_with_alpha(x + i, y + j,
+                source.get(i, j).clone(), source.get_alpha(i, j));
+            target.set_sample_count(x + i, y + j,
+                source.get_sample_count(i, j));
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::color::Color;
+use crate::render::RenderTarget;
+
+
+/// Curves for compressing high dynamic range colors into [0.0, 1.0].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    /// No compression; values above 1.0 are clipped by the encoder.
+    Clip,
+    /// Reinhard's L / (1 + L) curve, applied to luminance to preserve hue.
+    /// Channels of bright saturated colors can still go over 1.0, and are
+    /// clipped.
+    Reinhard,
+    /// John Hable's filmic curve from Uncharted 2.
+    Filmic,
+    /// Krzysztof Narkowicz's fit of the ACES reference rendering transform.
+    Aces
+}
+
+/// How tone mapped values are encoded before quantization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    /// Values are quantized as-is.
+    Linear,
+    /// The sRGB transfer curve, which is what most displays and image viewers
+    /// expect for 8-bit images.
+    Srgb
+}
+
+/// Post-processing settings for converting linear render output into
+/// displayable color. The defaults leave colors unchanged.
+#[derive(Debug, Clone)]
+pub struct ToneMapping {
+    /// Exposure adjustment in stops; each stop doubles the brightness.
+    pub exposure: f32,
+    /// Curve for compressing bright colors
+    pub operator: Operator,
+    /// Encoding applied after the curve
+    pub transfer: Transfer
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping {
+            exposure: 0.0,
+            operator: Operator::Clip,
+            transfer: Transfer::Linear
+        }
+    }
+}
+
+fn luminance(color: &Color) -> f32 {
+    0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b
+}
+
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn filmic(x: f32) -> f32 {
+    // Linear value that maps to white
+    const WHITE: f32 = 11.2;
+    // Hable's curve is quite dark without a boost to the input.
+    const EXPOSURE_BIAS: f32 = 2.0;
+    // The curve carries on rising past white, so it's clipped there.
+    (hable(x * EXPOSURE_BIAS) / hable(WHITE)).clamp(0.0, 1.0)
+}
+
+fn aces(x: f32) -> f32 {
+    const A: f32 = 2.51;
+    const B: f32 = 0.03;
+    const C: f32 = 2.43;
+    const D: f32 = 0.59;
+    const E: f32 = 0.14;
+    ((x * (A * x + B)) / (x * (C * x + D) + E)).clamp(0.0, 1.0)
+}
+
+fn srgb_encode(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl ToneMapping {
+    /// Map a linear color to a display color.
+    pub fn apply(&self, color: &Color) -> Color {
+        let exposed = color * 2f32.powf(self.exposure);
+
+        let mapped = match self.operator {
+            Operator::Clip => exposed,
+            Operator::Reinhard => {
+                let l = luminance(&exposed);
+                if l > 0.0 {
+                    let mut mapped = exposed * (1.0 / (1.0 + l));
+                    mapped.clamp();
+                    mapped
+                } else {
+                    exposed
+                }
+            },
+            Operator::Filmic => Color {
+                r: filmic(exposed.r),
+                g: filmic(exposed.g),
+                b: filmic(exposed.b)
+            },
+            Operator::Aces => Color {
+                r: aces(exposed.r),
+                g: aces(exposed.g),
+                b: aces(exposed.b)
+            }
+        };
+
+        match self.transfer {
+            Transfer::Linear => mapped,
+            Transfer::Srgb => Color {
+                r: srgb_encode(mapped.r.clamp(0.0, 1.0)),
+                g: srgb_encode(mapped.g.clamp(0.0, 1.0)),
+                b: srgb_encode(mapped.b.clamp(0.0, 1.0))
+            }
+        }
+    }
+
+    /// Apply the tone mapping to every pixel of `image`, leaving the alpha
+    /// channel as it is.
+    pub fn apply_to_image(&self, image: &RenderTarget) -> RenderTarget {
+        let mut result = RenderTarget::new(image.width, image.height);
+        for j in 0..image.height {
+            for i in 0..image.width {
+                result.set_with_alpha(i, j,
+                    self.apply(image.get(i, j)), image.get_alpha(i, j));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [Operator; 4] = [Operator::Clip, Operator::Reinhard,
+        Operator::Filmic, Operator::Aces];
+
+    fn tone_mapping(operator: Operator, transfer: Transfer) -> ToneMapping {
+        ToneMapping {exposure: 0.0, operator, transfer}
+    }
+
+    fn gray(value: f32) -> Color {
+        Color {r: value, g: value, b: value}
+    }
+
+    // Inputs from 0 up to 10000, closer together near 0
+    fn inputs() -> impl Iterator<Item = f32> {
+        (0..=1000).map(|i| (i as f32 / 100.0).powi(4))
+    }
+
+    #[test]
+    fn black_stays_black() {
+        for &operator in &OPERATORS {
+            for &transfer in &[Transfer::Linear, Transfer::Srgb] {
+                let mapped = tone_mapping(operator, transfer).apply(&gray(0.0));
+                for value in [mapped.r, mapped.g, mapped.b] {
+                    assert!(value.abs() < 1e-6, "{:?}", operator);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn monotonic() {
+        for &operator in &OPERATORS {
+            for &transfer in &[Transfer::Linear, Transfer::Srgb] {
+                let mapping = tone_mapping(operator, transfer);
+                let mut last = 0.0;
+                for input in inputs() {
+                    let mapped = mapping.apply(&gray(input));
+                    assert!(mapped.g >= last, "{:?} at {}", operator, input);
+                    last = mapped.g;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bright_colors_in_range() {
+        let colors = [gray(1e4), Color {r: 1e4, g: 0.0, b: 0.0},
+            Color {r: 0.0, g: 50.0, b: 1.0}, Color {r: 3.0, g: 2.0, b: 8.0}];
+        for &operator in &OPERATORS {
+            // Without compression, sRGB encoding still has to clip.
+            let transfers: &[Transfer] = if operator == Operator::Clip {
+                &[Transfer::Srgb]
+            } else {
+                &[Transfer::Linear, Transfer::Srgb]
+            };
+            for &transfer in transfers {
+                let mapping = tone_mapping(operator, transfer);
+                for color in colors.iter().cloned().chain(inputs().map(gray)) {
+                    let mapped = mapping.apply(&color);
+                    for value in [mapped.r, mapped.g, mapped.b] {
+                        assert!((0.0..=1.0).contains(&value),
+                            "{:?} gave {}", operator, value);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_untouched() {
+        let mut image = RenderTarget::new(2, 1);
+        image.set_with_alpha(0, 0, gray(4.0), 0.25);
+        image.set_with_alpha(1, 0, gray(0.5), 0.0);
+        for &operator in &OPERATORS {
+            let mapping = ToneMapping {
+                exposure: 1.5,
+                operator,
+                transfer: Transfer::Srgb
+            };
+            let mapped = mapping.apply_to_image(&image);
+            assert_eq!(mapped.get_alpha(0, 0), 0.25);
+            assert_eq!(mapped.get_alpha(1, 0), 0.0);
+        }
+    }
+
+    #[test]
+    fn srgb_transfer() {
+        let mapping = tone_mapping(Operator::Clip, Transfer::Srgb);
+        // Middle gray, on the power curve
+        let mapped = mapping.apply(&gray(0.214041));
+        assert!((mapped.r - 0.5).abs() < 1e-4);
+        // The linear segment near black
+        let mapped = mapping.apply(&gray(0.002));
+        assert!((mapped.r - 0.02584).abs() < 1e-6);
+        assert!((mapping.apply(&gray(1.0)).r - 1.0).abs() < 1e-6);
+
+        // Linear transfer with exposure just scales.
+        let mapping = ToneMapping {
+            exposure: 2.0,
+            operator: Operator::Clip,
+            transfer: Transfer::Linear
+        };
+        assert!((mapping.apply(&gray(0.1)).r - 0.4).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::vector_math;
+use crate::vector_math::{Point, Vector};
+use crate::shape::{Shape, IntersectResult, BoundingBox, Interval};
+use crate::csg::{self, Crossing};
+
+
+/// A ring-shaped torus: the surface swept by a circle of `minor_radius`
+/// whose center moves around a circle of `major_radius` about `axis`.
+pub struct Torus {
+    /// Center of the ring
+    pub center: Point,
+    /// Direction the ring goes around
+    pub axis: Vector,
+    /// Distance from the center to the middle of the tube
+    pub major_radius: f32,
+    /// Radius of the tube
+    pub minor_radius: f32
+}
+
+fn components(vector: &Vector) -> [f64; 3] {
+    [vector.dx as f64, vector.dy as f64, vector.dz as f64]
+}
+
+fn dot(v1: &[f64; 3], v2: &[f64; 3]) -> f64 {
+    v1[0] * v2[0] + v1[1] * v2[1] + v1[2] * v2[2]
+}
+
+// Where a ray meets a torus: the roots of the quartic in the distance along
+// the unit `direction` from `p`, which is `start` along it from the ray's
+// source. `length` is the length of the ray's own direction.
+struct Roots {
+    p: [f64; 3],
+    direction: [f64; 3],
+    start: f64,
+    length: f64,
+    distances: Vec<f64>
+}
+
+impl Torus {
+    // Everywhere the ray hits the torus from `near` on, in order along it
+    fn roots(&self, src: &Point, ray: &Vector, near: f32) -> Option<Roots> {
+        // The torus is the set of points p (relative to the center) where
+        //  (p . p + R^2 - r^2)^2 = 4 R^2 (p . p - (p . axis)^2)
+        // for major radius R and minor radius r. Substituting the ray
+        // equation for p gives a quartic in the distance along the ray.
+        //
+        // The quartic's coefficients lose precision when the ray starts far
+        // away compared to the size of the torus, so the ray is first moved
+        // up to where it enters a sphere enclosing the torus, and the work
+        // is done in double precision with a unit direction.
+        let direction = components(ray);
+        let length = dot(&direction, &direction).sqrt();
+        let direction = direction.map(|component| component / length);
+        let axis = components(&self.axis);
+        let axis_length = dot(&axis, &axis).sqrt();
+        let axis = axis.map(|component| component / axis_length);
+        let to_src = components(&(src - &self.center));
+        let major = self.major_radius as f64;
+        let minor = self.minor_radius as f64;
+
+        let outer = major + minor;
+        let m = dot(&to_src, &direction);
+        let discriminant = m * m - (dot(&to_src, &to_src) - outer * outer);
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sphere_far = -m + discriminant.sqrt();
+        let near = near as f64 * length;
+        if sphere_far < near {
+            return None;
+        }
+        let start = (-m - discriminant.sqrt()).max(near);
+
+        let p = [
+            to_src[0] + direction[0] * start,
+            to_src[1] + direction[1] * start,
+            to_src[2] + direction[2] * start];
+        let pd = dot(&p, &direction);
+        let pp = dot(&p, &p);
+        let pa = dot(&p, &axis);
+        let da = dot(&direction, &axis);
+        let k = pp + major * major - minor * minor;
+        let r2 = 4.0 * major * major;
+
+        // Rays grazing the outside of the ring enter the torus right where
+        // they enter the sphere, and rounding can put that just behind the
+        // new starting point, so look a little way back from it too.
+        let slack = 1e-9 * (start.abs() + outer);
+        let distances = vector_math::solve_quartic(&[
+                1.0,
+                4.0 * pd,
+                4.0 * pd * pd + 2.0 * k - r2 * (1.0 - da * da),
+                4.0 * pd * k - 2.0 * r2 * (pd - pa * da),
+                k * k - r2 * (pp - pa * pa)],
+            (near - start).max(-slack), sphere_far - start);
+
+        Some(Roots {p, direction, start, length, distances})
+    }
+
+    fn hit(&self, roots: &Roots, distance: f64) -> IntersectResult {
+        // The normal points away from the nearest point on the ring of
+        // centers of the swept circle.
+        let (p, direction) = (&roots.p, &roots.direction);
+        let hit = Vector {
+            dx: (p[0] + direction[0] * distance) as f32,
+            dy: (p[1] + direction[1] * distance) as f32,
+            dz: (p[2] + direction[2] * distance) as f32
+        };
+        let axis = self.axis.normalized();
+        let radial = &hit - &axis * vector_math::dot(&hit, &axis);
+        let radial_length = radial.magnitude();
+        let ring_point = if radial_length > 0.0 {
+            radial * (self.major_radius / radial_length)
+        } else {
+            radial
+        };
+
+        IntersectResult {
+            normal: (hit - ring_point).normalized(),
+            dist: ((roots.start + distance) / roots.length) as f32
+        }
+    }
+}
+
+impl Shape for Torus {
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        // The ring of centers of the swept circle reaches out along a world
+        // axis by the major radius times the sine of the angle between the
+        // world axis and the torus's axis. The swept circle adds its radius
+        // to that in every direction.
+        let axis = self.axis.normalized();
+        let reach = |component: f32| self.minor_radius +
+            self.major_radius * (1.0 - component * component).max(0.0).sqrt();
+        let reach = Vector {
+            dx: reach(axis.dx),
+            dy: reach(axis.dy),
+            dz: reach(axis.dz)
+        };
+
+        Some(BoundingBox {
+            corner: Point {
+                x: self.center.x - reach.dx,
+                y: self.center.y - reach.dy,
+                z: self.center.z - reach.dz
+            },
+            extent: reach * 2.0
+        })
+    }
+
+    fn intersect(&self, src: &Point, ray: &Vector, near: f32) ->
+            Option<IntersectResult> {
+        let roots = self.roots(src, ray, near)?;
+        let distance = *roots.distances.first()?;
+        Some(self.hit(&roots, distance))
+    }
+
+    // The torus is solid inside the tube. Which way the ray crosses it at
+    // each hit is told by the normal rather than by taking the hits in
+    // pairs, since a ray that grazes the tube only meets it once.
+    fn intervals(&self, src: &Point, ray: &Vector) -> Option<Vec<Interval>> {
+        let roots = match self.roots(src, ray, f32::NEG_INFINITY) {
+            Some(roots) => roots,
+            None => return Some(vec![])
+        };
+        let crossings = roots.distances.iter()
+            .map(|&distance| {
+                let hit = self.hit(&roots, distance);
+                let entering = vector_math::dot(&hit.normal, ray) < 0.0;
+                Crossing {hit, entering}
+            })
+            .collect();
+        Some(csg::closed_surface(crossings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_through_the_tube() {
+        let torus = Torus {
+            center: Point {x: 0.0, y: 0.0, z: 0.0},
+            axis: Vector {dx: 0.0, dy: 1.0, dz: 0.0},
+            major_radius: 2.0,
+            minor_radius: 0.5
+        };
+        let intervals = torus.intervals(&Point {x: -5.0, y: 0.0, z: 0.0},
+            &Vector {dx: 1.0, dy: 0.0, dz: 0.0}).unwrap();
+
+        let expected = [(2.5, 3.5), (6.5, 7.5)];
+        assert_eq!(intervals.len(), expected.len());
+        for (interval, (enter, exit)) in intervals.iter().zip(expected) {
+            assert!((interval.enter.dist - enter).abs() < 1e-4);
+            assert!((interval.exit.dist - exit).abs() < 1e-4);
+            assert!(interval.enter.normal.dx < 0.0);
+            assert!(interval.exit.normal.dx > 0.0);
+        }
+    }
+
+    #[test]
+    fn ray_grazing_the_tube() {
+        let torus = Torus {
+            center: Point {x: 0.0, y: 0.0, z: 0.0},
+            axis: Vector {dx: 0.0, dy: 1.0, dz: 0.0},
+            major_radius: 2.0,
+            minor_radius: 0.5
+        };
+        let ray = Vector {dx: 1.0, dy: 0.0, dz: 0.0};
+
+        // Along the top of the tube, the ray just touches it at x = -2 and
+        // x = 2, where the quartic has double roots.
+        let src = Point {x: -5.0, y: 0.5, z: 0.0};
+        let hit = torus.intersect(&src, &ray, 0.0).unwrap();
+        assert!((hit.dist - 3.0).abs() < 1e-4);
+        assert!((hit.normal.dy - 1.0).abs() < 1e-3);
+        let hit = torus.intersect(&src, &ray, 4.0).unwrap();
+        assert!((hit.dist - 7.0).abs() < 1e-4);
+        assert!((hit.normal.dy - 1.0).abs() < 1e-3);
+        // Touching the surface doesn't enclose anything.
+        assert!(torus.intervals(&src, &ray).unwrap().iter()
+            .all(|interval| interval.exit.dist - interval.enter.dist < 1e-3));
+
+        // Just above, it misses.
+        let src = Point {x: -5.0, y: 0.501, z: 0.0};
+        assert!(torus.intersect(&src, &ray, 0.0).is_none());
+        assert!(torus.intervals(&src, &ray).unwrap().is_empty());
+    }
+}
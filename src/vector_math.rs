@@ -1,64 +1,91 @@
 use std::ops;
 
+/// A position in space.
 #[derive(Debug, Clone)]
 pub struct Point {
+    /// x coordinate
     pub x: f32,
+    /// y coordinate
     pub y: f32,
+    /// z coordinate
     pub z: f32
 }
 
+/// A direction and length in space, such as the difference between two
+/// points.
 #[derive(Debug, Clone)]
 pub struct Vector {
+    /// Component along x
     pub dx: f32,
+    /// Component along y
     pub dy: f32,
+    /// Component along z
     pub dz: f32
 }
 
+/// One of the three coordinate axes.
 #[derive(Debug, Clone, Copy)]
 pub enum Axis {
+    /// The x axis
     X,
+    /// The y axis
     Y,
+    /// The z axis
     Z
 }
 
 impl Vector {
+    /// Length of the vector
     pub fn magnitude(&self) -> f32 {
         let m2 = self.dx * self.dx + self.dy * self.dy + self.dz * self.dz;
         m2.sqrt()
     }
 
+    /// Scale the vector to unit length.
     pub fn normalize(&mut self) {
         let mag = self.magnitude();
         *self /= mag;
     }
 
+    /// Vector in the same direction with unit length
     pub fn normalized(&self) -> Vector {
         let mag = self.magnitude();
         self / mag
     }
 
+    /// Dot product with `other`
     pub fn dot(&self, other: &Vector) -> f32 {
         dot(self, other)
     }
 
-    //  normal   result
-    //        \  ^
-    //         \ | __ surface
-    //          \|/
-    // self ---->*
-    //          /
-    //         /
+    /// Mirror image of the vector bouncing off a surface with `normal`:
+    ///
+    /// ```text
+    ///  normal   result
+    ///        \  ^
+    ///         \ | __ surface
+    ///          \|/
+    /// self ---->*
+    ///          /
+    ///         /
+    /// ```
     pub fn reflected(&self, normal: &Vector) -> Vector {
         self - 2.0 * self.dot(normal) * normal
     }
 
-    //             | surface
-    //             |
-    //   self ---->|
-    // normal <----|\
-    //             | \
-    //             |  \
-    //                 result
+    /// Direction of the vector after passing through a surface with `normal`
+    /// into a material with the given relative index of refraction, or the
+    /// reflected direction if it's totally internally reflected:
+    ///
+    /// ```text
+    ///             | surface
+    ///             |
+    ///   self ---->|
+    /// normal <----|\
+    ///             | \
+    ///             |  \
+    ///                 result
+    /// ```
     pub fn refracted(&self, normal: &Vector, refraction_index: f32) -> Vector {
         let dp = self.dot(normal);
         let a = 1.0 - ((1.0 - dp * dp) / (refraction_index * refraction_index));
@@ -72,6 +99,7 @@ impl Vector {
         }
     }
 
+    /// Component along `axis`
     pub fn component(&self, axis: Axis) -> f32 {
         match axis {
             Axis::X => self.dx,
@@ -82,6 +110,7 @@ impl Vector {
 }
 
 impl Point {
+    /// The point (0, 0, 0)
     pub fn origin() -> Point {
         Point {
             x: 0.0,
@@ -90,6 +119,7 @@ impl Point {
         }
     }
 
+    /// Coordinate along `axis`
     pub fn component(&self, axis: Axis) -> f32 {
         match axis {
             Axis::X => self.x,
@@ -99,9 +129,12 @@ impl Point {
     }
 }
 
+/// A point on a surface and the surface normal there.
 #[derive(Debug, Clone)]
 pub struct PointNormal {
+    /// Position of the point
     pub point: Point,
+    /// Normal at the point
     pub normal: Vector
 }
 
@@ -446,3 +479,512 @@ pub fn interpolate(v1: &Vector, v2: &Vector, scale: f32) -> Vector {
     result.normalize();
     result
 }
+
+// Rotate `v` by `degrees` about `axis`, counter-clockwise when looking back
+// along the axis (Rodrigues' rotation formula).
+pub fn rotate(v: &Vector, axis: &Vector, degrees: f32) -> Vector {
+    let axis = axis.normalized();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    v * cos + cross(&axis, v) * sin + &axis * (dot(&axis, v) * (1.0 - cos))
+}
+
+/// An affine transformation, such as any combination of translations,
+/// rotations and scalings. It's stored as the top three rows of a 4x4
+/// matrix, whose last column is the translation, along with the matrix of
+/// the inverse transformation so it can be undone cheaply.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    matrix: [[f32; 4]; 3],
+    inverse: [[f32; 4]; 3]
+}
+
+// Product of two affine matrices, which transforms by `b` and then by `a`
+fn multiply(a: &[[f32; 4]; 3], b: &[[f32; 4]; 3]) -> [[f32; 4]; 3] {
+    let mut result = [[0.0; 4]; 3];
+    for (row, a_row) in result.iter_mut().zip(a.iter()) {
+        for (column, value) in row.iter_mut().enumerate() {
+            *value = a_row[0] * b[0][column] + a_row[1] * b[1][column] +
+                a_row[2] * b[2][column];
+        }
+        row[3] += a_row[3];
+    }
+    result
+}
+
+impl Transform {
+    /// Transform that leaves everything where it is
+    pub fn identity() -> Transform {
+        let matrix = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0]];
+        Transform {
+            matrix,
+            inverse: matrix
+        }
+    }
+
+    /// Transform from the top three rows of a 4x4 matrix, or None if it
+    /// can't be inverted, e.g. because it squashes everything flat.
+    pub fn from_matrix(matrix: [[f32; 4]; 3]) -> Option<Transform> {
+        // Invert the upper left 3x3 block A by way of its adjugate, in
+        // double precision; the inverse translation is then -A^-1 t.
+        let m = matrix.map(|row| row.map(|value| value as f64));
+        let adjugate = [
+            [m[1][1] * m[2][2] - m[1][2] * m[2][1],
+                m[0][2] * m[2][1] - m[0][1] * m[2][2],
+                m[0][1] * m[1][2] - m[0][2] * m[1][1]],
+            [m[1][2] * m[2][0] - m[1][0] * m[2][2],
+                m[0][0] * m[2][2] - m[0][2] * m[2][0],
+                m[0][2] * m[1][0] - m[0][0] * m[1][2]],
+            [m[1][0] * m[2][1] - m[1][1] * m[2][0],
+                m[0][1] * m[2][0] - m[0][0] * m[2][1],
+                m[0][0] * m[1][1] - m[0][1] * m[1][0]]];
+        let determinant = m[0][0] * adjugate[0][0] +
+            m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+
+        let mut inverse = [[0.0; 4]; 3];
+        for (i, row) in inverse.iter_mut().enumerate() {
+            let a = adjugate[i].map(|value| value / determinant);
+            for (j, value) in row.iter_mut().take(3).enumerate() {
+                *value = a[j] as f32;
+            }
+            row[3] = -(a[0] * m[0][3] + a[1] * m[1][3] + a[2] * m[2][3]) as f32;
+        }
+
+        Some(Transform {
+            matrix,
+            inverse
+        })
+    }
+
+    /// Move by `offset`.
+    pub fn translation(offset: &Vector) -> Transform {
+        let (x, y, z) = (offset.dx, offset.dy, offset.dz);
+        Transform {
+            matrix: [
+                [1.0, 0.0, 0.0, x],
+                [0.0, 1.0, 0.0, y],
+                [0.0, 0.0, 1.0, z]],
+            inverse: [
+                [1.0, 0.0, 0.0, -x],
+                [0.0, 1.0, 0.0, -y],
+                [0.0, 0.0, 1.0, -z]]
+        }
+    }
+
+    /// Scale by the given factors along the x, y and z axes, which must not
+    /// be zero.
+    pub fn scaling(factors: &Vector) -> Transform {
+        let (x, y, z) = (factors.dx, factors.dy, factors.dz);
+        Transform {
+            matrix: [
+                [x, 0.0, 0.0, 0.0],
+                [0.0, y, 0.0, 0.0],
+                [0.0, 0.0, z, 0.0]],
+            inverse: [
+                [1.0 / x, 0.0, 0.0, 0.0],
+                [0.0, 1.0 / y, 0.0, 0.0],
+                [0.0, 0.0, 1.0 / z, 0.0]]
+        }
+    }
+
+    /// Turn by `degrees` about `axis`, in the same sense as `rotate`.
+    pub fn rotation(axis: &Vector, degrees: f32) -> Transform {
+        let columns = [
+            rotate(&Vector {dx: 1.0, dy: 0.0, dz: 0.0}, axis, degrees),
+            rotate(&Vector {dx: 0.0, dy: 1.0, dz: 0.0}, axis, degrees),
+            rotate(&Vector {dx: 0.0, dy: 0.0, dz: 1.0}, axis, degrees)];
+
+        // A rotation's inverse is its transpose.
+        let mut matrix = [[0.0; 4]; 3];
+        let mut inverse = [[0.0; 4]; 3];
+        for (i, column) in columns.iter().enumerate() {
+            let components = [column.dx, column.dy, column.dz];
+            for (j, &component) in components.iter().enumerate() {
+                matrix[j][i] = component;
+                inverse[i][j] = component;
+            }
+        }
+
+        Transform {
+            matrix,
+            inverse
+        }
+    }
+
+    /// This transformation followed by `next`
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform {
+            matrix: multiply(&next.matrix, &self.matrix),
+            inverse: multiply(&self.inverse, &next.inverse)
+        }
+    }
+
+    /// Transform that undoes this one
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix
+        }
+    }
+
+    /// Transform a point.
+    pub fn apply_point(&self, point: &Point) -> Point {
+        let m = &self.matrix;
+        let (x, y, z) = (point.x, point.y, point.z);
+        Point {
+            x: m[0][0] * x + m[0][1] * y + m[0][2] * z + m[0][3],
+            y: m[1][0] * x + m[1][1] * y + m[1][2] * z + m[1][3],
+            z: m[2][0] * x + m[2][1] * y + m[2][2] * z + m[2][3]
+        }
+    }
+
+    /// Transform a direction, which unlike a point isn't moved by the
+    /// translation.
+    pub fn apply_vector(&self, vector: &Vector) -> Vector {
+        let m = &self.matrix;
+        let (x, y, z) = (vector.dx, vector.dy, vector.dz);
+        Vector {
+            dx: m[0][0] * x + m[0][1] * y + m[0][2] * z,
+            dy: m[1][0] * x + m[1][1] * y + m[1][2] * z,
+            dz: m[2][0] * x + m[2][1] * y + m[2][2] * z
+        }
+    }
+
+    /// Transform a surface normal. Normals have to stay at right angles to
+    /// the transformed surface, which takes the transpose of the inverse
+    /// matrix rather than the matrix itself when there's non-uniform
+    /// scaling. The result isn't normalized.
+    pub fn apply_normal(&self, normal: &Vector) -> Vector {
+        let m = &self.inverse;
+        let (x, y, z) = (normal.dx, normal.dy, normal.dz);
+        Vector {
+            dx: m[0][0] * x + m[1][0] * y + m[2][0] * z,
+            dy: m[0][1] * x + m[1][1] * y + m[2][1] * z,
+            dz: m[0][2] * x + m[1][2] * y + m[2][2] * z
+        }
+    }
+}
+
+// Value of the polynomial with `coefficients` (highest power first) at `x`,
+// and the value of its derivative there, by Horner's method.
+fn evaluate_polynomial(coefficients: &[f64], x: f64) -> (f64, f64) {
+    let mut value = 0.0;
+    let mut derivative = 0.0;
+    for &coefficient in coefficients {
+        derivative = derivative * x + value;
+        value = value * x + coefficient;
+    }
+    (value, derivative)
+}
+
+// Whether the polynomial with `coefficients` is zero at `x`, give or take
+// the rounding error in evaluating it, which scales with the magnitude of
+// its terms there.
+fn touches_zero(coefficients: &[f64], x: f64) -> bool {
+    let (value, _) = evaluate_polynomial(coefficients, x);
+    let magnitude = coefficients.iter()
+        .fold(0.0, |sum: f64, &coefficient| sum * x.abs() + coefficient.abs());
+    value.abs() <= 1e-12 * magnitude
+}
+
+// Find the root of a polynomial between `min` and `max`, where it's known to
+// be monotonic, if there is one. Newton's method converges quickly, but the
+// root stays bracketed and bisection takes over whenever a Newton step would
+// leave the bracket, so it can't diverge or get stuck.
+fn monotonic_root(coefficients: &[f64], min: f64, max: f64) -> Option<f64> {
+    let (min_value, _) = evaluate_polynomial(coefficients, min);
+    let (max_value, _) = evaluate_polynomial(coefficients, max);
+    if min_value == 0.0 {
+        return Some(min);
+    }
+    if max_value == 0.0 {
+        return Some(max);
+    }
+    if (min_value < 0.0) == (max_value < 0.0) {
+        return None;
+    }
+
+    let (mut low, mut high) = (min, max);
+    let mut x = 0.5 * (low + high);
+    for _ in 0..100 {
+        let (value, derivative) = evaluate_polynomial(coefficients, x);
+        if value == 0.0 {
+            break;
+        }
+        if (value < 0.0) == (min_value < 0.0) {
+            low = x;
+        } else {
+            high = x;
+        }
+
+        let newton = x - value / derivative;
+        let next = if newton > low && newton < high {
+            newton
+        } else {
+            0.5 * (low + high)
+        };
+        let converged = (next - x).abs() <= 1e-14 * (1.0 + x.abs());
+        x = next;
+        if converged {
+            break;
+        }
+    }
+    Some(x)
+}
+
+// Real roots of the polynomial with `coefficients` (highest power first)
+// between `min` and `max`, in ascending order. The roots of the derivative
+// split the range into pieces where the polynomial is monotonic, which then
+// have at most one root each; they're found the same way, recursively. This
+// avoids the closed-form solutions for cubics and quartics, which lose badly
+// to cancellation for some inputs. Double roots, where the polynomial only
+// touches zero, are found once, as long as it comes within rounding error of
+// zero at the turning point.
+pub fn polynomial_roots(coefficients: &[f64], min: f64, max: f64) ->
+        Vec<f64> {
+    // Zero leading coefficients just lower the degree.
+    let first = match coefficients.iter().position(|&c| c != 0.0) {
+        Some(first) => first,
+        None => return vec![]
+    };
+    let coefficients = &coefficients[first..];
+    let degree = coefficients.len() - 1;
+
+    match degree {
+        0 => vec![],
+        1 => {
+            let root = -coefficients[1] / coefficients[0];
+            if root >= min && root <= max {
+                vec![root]
+            } else {
+                vec![]
+            }
+        },
+        _ => {
+            let derivative: Vec<f64> = coefficients[..degree].iter()
+                .enumerate()
+                .map(|(i, &c)| c * (degree - i) as f64)
+                .collect();
+
+            let mut bounds = vec![min];
+            bounds.extend(polynomial_roots(&derivative, min, max));
+            bounds.push(max);
+
+            // Rounding can leave a turning point that touches zero a little
+            // either side of it, which would drop the double root or split
+            // it in two, so one that close counts as the root of both the
+            // pieces on either side.
+            let last = bounds.len() - 1;
+            let touches: Vec<bool> = bounds.iter().enumerate()
+                .map(|(i, &x)| {
+                    i > 0 && i < last && touches_zero(coefficients, x)
+                })
+                .collect();
+
+            let mut roots: Vec<f64> = vec![];
+            for (i, piece) in bounds.windows(2).enumerate() {
+                let root = if touches[i] {
+                    Some(piece[0])
+                } else if touches[i + 1] {
+                    Some(piece[1])
+                } else {
+                    monotonic_root(coefficients, piece[0], piece[1])
+                };
+                if let Some(root) = root {
+                    // A root on the boundary between two pieces is found
+                    // in both.
+                    if roots.last() != Some(&root) {
+                        roots.push(root);
+                    }
+                }
+            }
+            roots
+        }
+    }
+}
+
+// Real roots of a x^4 + b x^3 + c x^2 + d x + e between `min` and `max`,
+// where `coefficients` is [a, b, c, d, e], in ascending order.
+pub fn solve_quartic(coefficients: &[f64; 5], min: f64, max: f64) ->
+        Vec<f64> {
+    polynomial_roots(coefficients, min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Coefficients, highest power first, of the monic polynomial with
+    // `roots`.
+    fn from_roots(roots: &[f64]) -> Vec<f64> {
+        let mut coefficients = vec![1.0];
+        for &root in roots {
+            coefficients.push(0.0);
+            for i in (1..coefficients.len()).rev() {
+                coefficients[i] -= root * coefficients[i - 1];
+            }
+        }
+        coefficients
+    }
+
+    fn assert_roots(found: &[f64], expected: &[f64]) {
+        assert_eq!(found.len(), expected.len(), "{:?}", found);
+        for (found, expected) in found.iter().zip(expected) {
+            assert!((found - expected).abs() <= 1e-6 * expected.abs().max(1.0),
+                "{:?}", found);
+        }
+    }
+
+    #[test]
+    fn distinct_roots() {
+        let coefficients = [1.0, -10.0, 35.0, -50.0, 24.0];
+        assert_eq!(from_roots(&[1.0, 2.0, 3.0, 4.0]), coefficients);
+        assert_roots(&solve_quartic(&coefficients, 0.0, 5.0),
+            &[1.0, 2.0, 3.0, 4.0]);
+        assert_roots(&solve_quartic(&coefficients, -100.0, 100.0),
+            &[1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn double_roots() {
+        for &double in &[1.0, 0.3, -0.7, 1.0 / 3.0, 2.5e-3] {
+            let coefficients = from_roots(&[double, -1.0, double, 2.0]);
+            let roots = polynomial_roots(&coefficients, -5.0, 5.0);
+            assert_roots(&roots, &[-1.0, double, 2.0]);
+        }
+
+        // Two double roots, with no sign change anywhere.
+        let coefficients = from_roots(&[0.6, 0.6, 1.7, 1.7]);
+        assert_roots(&polynomial_roots(&coefficients, -5.0, 5.0), &[0.6, 1.7]);
+    }
+
+    #[test]
+    fn no_roots() {
+        assert!(solve_quartic(&[1.0, 0.0, 0.0, 0.0, 1.0], -10.0, 10.0)
+            .is_empty());
+        // (x^2 + 1) (x^2 - 2x + 2) has turning points, but stays above zero.
+        assert!(solve_quartic(&[1.0, -2.0, 3.0, -2.0, 2.0], -10.0, 10.0)
+            .is_empty());
+        // Just missing a double root isn't a root.
+        let mut coefficients = from_roots(&[0.5, 0.5, 3.0, 4.0]);
+        coefficients[4] += 1e-6;
+        assert_roots(&polynomial_roots(&coefficients, -1.0, 2.0), &[]);
+        assert!(polynomial_roots(&[0.0, 0.0, 2.0], -1.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn roots_outside_range() {
+        let coefficients = [1.0, -10.0, 35.0, -50.0, 24.0];
+        assert_roots(&solve_quartic(&coefficients, 1.5, 3.5), &[2.0, 3.0]);
+        assert!(solve_quartic(&coefficients, 4.5, 10.0).is_empty());
+        assert!(solve_quartic(&coefficients, -10.0, 0.5).is_empty());
+        // Roots on the ends of the range are included.
+        assert_roots(&solve_quartic(&coefficients, 2.0, 3.0), &[2.0, 3.0]);
+    }
+
+    #[test]
+    fn widely_spread_coefficients() {
+        let expected = [1e-3, 1.0, 1e3, 1e5];
+        let coefficients = from_roots(&expected);
+        let roots = polynomial_roots(&coefficients, -1e6, 1e6);
+        assert_eq!(roots.len(), 4, "{:?}", roots);
+        for (root, expected) in roots.iter().zip(&expected) {
+            assert!((root - expected).abs() <= 1e-9 * expected, "{:?}", roots);
+        }
+
+        // A scaled polynomial has the same roots.
+        let scaled: Vec<f64> = coefficients.iter().map(|c| c * 1e-20).collect();
+        assert_eq!(polynomial_roots(&scaled, -1e6, 1e6).len(), 4);
+    }
+
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {x, y, z}
+    }
+
+    fn vector(dx: f32, dy: f32, dz: f32) -> Vector {
+        Vector {dx, dy, dz}
+    }
+
+    fn assert_close(found: &Point, expected: &Point) {
+        assert!((found - expected).magnitude() < 1e-5, "{:?}", found);
+    }
+
+    // Some transforms that are hard to mistake for each other
+    fn transforms() -> Vec<Transform> {
+        vec![
+            Transform::translation(&vector(1.0, -2.0, 3.0)),
+            Transform::scaling(&vector(2.0, 0.5, -3.0)),
+            Transform::rotation(&vector(1.0, 2.0, -1.0).normalized(), 30.0),
+            Transform::from_matrix([
+                [1.0, 0.5, 0.0, 2.0],
+                [0.0, 2.0, 0.3, -1.0],
+                [0.2, 0.0, 1.5, 0.5]]).unwrap()]
+    }
+
+    #[test]
+    fn then_applies_in_order() {
+        // Moving and then scaling scales the move too.
+        let moved = Transform::translation(&vector(1.0, 0.0, 0.0));
+        let scaled = Transform::scaling(&vector(2.0, 3.0, 4.0));
+        assert_close(&moved.then(&scaled).apply_point(&Point::origin()),
+            &point(2.0, 0.0, 0.0));
+        assert_close(&scaled.then(&moved).apply_point(&Point::origin()),
+            &point(1.0, 0.0, 0.0));
+
+        let p = point(0.3, -1.2, 2.5);
+        for t in transforms() {
+            for u in transforms() {
+                let combined = t.then(&u);
+                assert_close(&combined.apply_point(&p),
+                    &u.apply_point(&t.apply_point(&p)));
+                let v = combined.apply_vector(&vector(p.x, p.y, p.z));
+                let expected =
+                    u.apply_vector(&t.apply_vector(&vector(p.x, p.y, p.z)));
+                assert!((v - expected).magnitude() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_transform() {
+        let points = [point(0.0, 0.0, 0.0), point(0.3, -1.2, 2.5),
+            point(-4.0, 1.0, 0.5)];
+        for t in transforms() {
+            for undone in &[t.inverse().then(&t), t.then(&t.inverse())] {
+                for p in &points {
+                    assert_close(&undone.apply_point(p), p);
+                }
+                let normal = undone.apply_normal(&vector(0.0, 0.6, 0.8));
+                assert!((normal - vector(0.0, 0.6, 0.8)).magnitude() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let scaled = Transform::scaling(&vector(4.0, 1.0, 0.25))
+            .then(&Transform::rotation(&vector(0.0, 0.0, 1.0), 40.0));
+        let normal = vector(1.0, 1.0, 1.0).normalized();
+        let tangents = [vector(1.0, -1.0, 0.0), vector(0.0, 1.0, -1.0),
+            vector(2.0, -1.0, -1.0)];
+        for t in transforms().iter().chain(Some(&scaled)) {
+            let transformed = t.apply_normal(&normal).normalized();
+            for tangent in &tangents {
+                assert!(normal.dot(tangent).abs() < 1e-6);
+                let tangent = t.apply_vector(tangent).normalized();
+                assert!(transformed.dot(&tangent).abs() < 1e-5);
+            }
+        }
+
+        // Transforming the normal like a direction would tilt it.
+        let wrong = scaled.apply_vector(&normal).normalized();
+        let tangent = scaled.apply_vector(&tangents[0]).normalized();
+        assert!(wrong.dot(&tangent).abs() > 0.1);
+    }
+}
+